@@ -0,0 +1,27 @@
+// Messages announcing that a connection was lost or re-established, per `--connection-events`.
+use crate::format::SdElement;
+use crate::sender::ConnectionEvent;
+
+/// The MSGID of the message for a lost connection.
+pub const CONN_DOWN_MSGID: &str = "CONN_DOWN";
+/// The MSGID of the message for a re-established connection.
+pub const CONN_UP_MSGID: &str = "CONN_UP";
+
+/// Builds the MSGID, SD-ELEMENT and MSG announcing the event.
+pub fn message(event: &ConnectionEvent) -> (&'static str, SdElement, String) {
+    match event {
+        ConnectionEvent::Down { peer, error } => (
+            CONN_DOWN_MSGID,
+            SdElement::new("conn@32473").param("peer", peer).param("error", error),
+            format!("Lost connection to {peer}: {error}\n"),
+        ),
+        ConnectionEvent::Up { peer, downtime, attempts } => (
+            CONN_UP_MSGID,
+            SdElement::new("conn@32473")
+                .param("peer", peer)
+                .param("downtime-ms", downtime.as_millis())
+                .param("attempts", attempts),
+            format!("Reconnected to {peer} after {:.3}s, on attempt {attempts}\n", downtime.as_secs_f64()),
+        ),
+    }
+}
@@ -0,0 +1,37 @@
+// Coalescing runs of identical lines, in the style of syslogd's "last message repeated N times".
+use std::sync::atomic::Ordering;
+
+use crate::stats::Stats;
+
+#[derive(Debug, Default)]
+pub struct Deduplicator {
+    last_line: Option<String>,
+    repeats: u64,
+}
+
+impl Deduplicator {
+    /// Returns the lines that should be forwarded now that `line` has arrived: nothing if it
+    /// repeats the previous line, otherwise any pending repeat summary followed by `line`.
+    pub fn push(&mut self, line: String, stats: &Stats) -> Vec<String> {
+        if self.last_line.as_ref() == Some(&line) {
+            self.repeats += 1;
+            stats.duplicates_suppressed.fetch_add(1, Ordering::Relaxed);
+            return vec![];
+        }
+        let mut lines: Vec<String> = self.flush().into_iter().collect();
+        lines.push(line.clone());
+        self.last_line = Some(line);
+        lines
+    }
+
+    /// Returns the summary for any repeats withheld since the last forwarded line. The
+    /// previous line is still remembered, so further repeats continue to be coalesced.
+    pub fn flush(&mut self) -> Option<String> {
+        if self.repeats == 0 {
+            return None;
+        }
+        let summary = format!("last message repeated {} times\n", self.repeats);
+        self.repeats = 0;
+        Some(summary)
+    }
+}
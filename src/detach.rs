@@ -0,0 +1,56 @@
+// Handing the rest of the run off to a detached copy of the wrapper, per `--no-wait-child`.
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::FromRawFd;
+
+/// Held by the detached wrapper until delivery is up, at which point the original wrapper is
+/// told it can exit.
+pub struct Detached {
+    ready: File,
+}
+
+/// Forks, returning only in the detached child, which starts a session of its own so that it
+/// outlives the original wrapper's terminal and process group. The original wrapper waits for
+/// `Detached::delivery_established` and exits 0; if the detached wrapper exits first, the
+/// original one exits with the same code.
+///
+/// Must be called while the process still has just the one thread.
+pub fn detach() -> Detached {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        eprintln!("Unable to detach: {}", std::io::Error::last_os_error());
+        crate::exit(40);
+    }
+    let (mut ready_reader, ready_writer) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    match unsafe { libc::fork() } {
+        -1 => {
+            eprintln!("Unable to detach: {}", std::io::Error::last_os_error());
+            crate::exit(40);
+        },
+        0 => {
+            drop(ready_reader);
+            unsafe { libc::setsid() };
+            Detached { ready: ready_writer }
+        },
+        pid => {
+            drop(ready_writer);
+            let mut ready = [0];
+            if ready_reader.read(&mut ready).unwrap_or(0) == 1 {
+                std::process::exit(0);
+            }
+            // The detached wrapper exited without getting delivery going
+            let mut status = 0;
+            if unsafe { libc::waitpid(pid, &mut status, 0) } == -1 || !libc::WIFEXITED(status) {
+                std::process::exit(40);
+            }
+            std::process::exit(libc::WEXITSTATUS(status));
+        },
+    }
+}
+
+impl Detached {
+    pub fn delivery_established(mut self) {
+        // If the original wrapper has already gone, there's nobody left to tell
+        let _ = self.ready.write_all(b"1");
+    }
+}
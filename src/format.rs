@@ -0,0 +1,239 @@
+// Formatting captured lines as RFC 5424 syslog messages.
+use std::fmt;
+use std::string::FromUtf8Error;
+
+use base64::Engine;
+use chrono::{DateTime, Utc}; // Formatting UTC time for syslog protocol
+
+use crate::priority::Priority;
+
+pub const DEFAULT_SYSLOG_VERSION: u16 = 1; // See RFC 5424 sec. 6.2.2
+/// The VERSION values the formatter emits a correct header for. Others are refused rather than
+/// sent in a header whose fields may not mean what the collector expects from that version.
+pub const SYSLOG_VERSIONS: &[u16] = &[DEFAULT_SYSLOG_VERSION];
+pub const MAX_HOSTNAME_LEN: usize = 255; // See RFC 5424 sec. 6.2.4
+pub const MAX_APPNAME_LEN: usize = 48; // See RFC 5424 sec. 6.2.5
+pub const MAX_SD_NAME_LEN: usize = 32; // See RFC 5424 sec. 6.3.2
+
+/// How a captured line is carried in the MSG part of the message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum MsgEncoding {
+    /// The line as written, which must be valid UTF-8.
+    Raw,
+    /// The line's exact bytes, base64-encoded, flagged with an `encoding@32473` SD-ELEMENT.
+    Base64,
+}
+
+impl MsgEncoding {
+    /// Turns a line read from the command, including its line break, into MSG text. A raw
+    /// line's CRLF line break is normalized to LF; base64 keeps every byte.
+    pub fn encode(&self, mut line: Vec<u8>) -> Result<String, FromUtf8Error> {
+        match self {
+            MsgEncoding::Raw => {
+                // Otherwise collectors see a stray carriage return at the end of MSG
+                if line.ends_with(b"\r\n") {
+                    line.remove(line.len() - 2);
+                } else if line.ends_with(b"\r") {
+                    line.pop();
+                }
+                String::from_utf8(line)
+            },
+            MsgEncoding::Base64 => {
+                // The line break is framing, not content, so it stays outside the encoding
+                let content = line.strip_suffix(b"\n").unwrap_or(&line);
+                let mut encoded = base64::engine::general_purpose::STANDARD.encode(content);
+                if content.len() < line.len() {
+                    encoded.push('\n');
+                }
+                Ok(encoded)
+            },
+        }
+    }
+
+    /// Turns MSG text back into the line it was encoded from, as far as that's possible: a raw
+    /// line's normalized CRLF stays normalized.
+    pub fn decode(&self, msg: &str) -> Vec<u8> {
+        match self {
+            MsgEncoding::Raw => msg.as_bytes().to_vec(),
+            MsgEncoding::Base64 => {
+                let content = msg.strip_suffix('\n').unwrap_or(msg);
+                let mut line = base64::engine::general_purpose::STANDARD.decode(content)
+                    .expect("MSG text was base64-encoded by `encode`.");
+                if content.len() < msg.len() {
+                    line.push(b'\n');
+                }
+                line
+            },
+        }
+    }
+
+    /// The SD-ELEMENT that tells the collector how to decode MSG, if it needs telling.
+    pub fn sd_element(&self) -> Option<SdElement> {
+        match self {
+            MsgEncoding::Raw => None,
+            MsgEncoding::Base64 => Some(SdElement::new("encoding@32473").param("type", "base64")),
+        }
+    }
+}
+
+/// One element of a message's STRUCTURED-DATA, per RFC 5424 sec. 6.3.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SdElement {
+    pub id: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl SdElement {
+    pub fn new(id: &str) -> SdElement {
+        SdElement { id: id.into(), params: vec![] }
+    }
+
+    pub fn param(mut self, name: &str, value: impl ToString) -> SdElement {
+        self.params.push((name.into(), value.to_string()));
+        self
+    }
+
+    /// Parses `SD-ID [NAME=VALUE ...]`, as in `meta@32473 env=prod region=eu`. A value runs to
+    /// the next space, unless it's quoted, as in `note="two words"`, where `\"` and `\\` stand
+    /// for `"` and `\`.
+    pub fn parse(value: &str) -> Result<SdElement, String> {
+        let value = value.trim();
+        let (id, mut rest) = value.split_once(' ').unwrap_or((value, ""));
+        check_sd_name(id)?;
+        let mut element = SdElement::new(id);
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                return Ok(element);
+            }
+            let (name, after) = rest.split_once('=')
+                .ok_or_else(|| format!("`{rest}` is not of the form NAME=VALUE"))?;
+            check_sd_name(name)?;
+            let (param_value, after) = match after.strip_prefix('"') {
+                Some(quoted) => unquote(quoted).ok_or_else(|| format!("The value of `{name}` has no closing quote"))?,
+                None => {
+                    let end = after.find(' ').unwrap_or(after.len());
+                    (after[..end].to_string(), &after[end..])
+                },
+            };
+            element = element.param(name, param_value);
+            rest = after;
+        }
+    }
+}
+
+/// Splits a quoted value (after its opening quote) from whatever follows its closing quote,
+/// undoing backslash escapes. `None` if there is no closing quote.
+fn unquote(quoted: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => value.push(chars.next()?.1),
+            '"' => return Some((value, &quoted[i + 1..])),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Checks that `name` is allowed as an SD-ID or PARAM-NAME: 1 to 32 printable ASCII
+/// characters, other than `=`, space, `]` and `"`.
+pub fn check_sd_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_SD_NAME_LEN {
+        return Err(format!("`{name}` is not 1 to {MAX_SD_NAME_LEN} characters long, as structured data names must be"));
+    }
+    match name.chars().find(|c| !c.is_ascii_graphic() || matches!(c, '=' | ']' | '"')) {
+        Some(c) => Err(format!("`{name}` contains {c:?}, which structured data names can't")),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for SdElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}", self.id)?;
+        for (name, value) in &self.params {
+            // Per RFC 5424 sec. 6.3.3, these three must be escaped within PARAM-VALUE
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]");
+            write!(f, " {name}=\"{value}\"")?;
+        }
+        write!(f, "]")
+    }
+}
+
+#[derive(Debug)]
+pub struct Formatter {
+    pub hostname: String,
+    pub appname: String,
+    pub encoding: MsgEncoding,
+    /// The VERSION header field. Only version 1 is defined, but some collectors expect others.
+    pub version: u16,
+}
+
+impl Formatter {
+    /// `msgid` identifies messages of a particular type, such as those the wrapper generates
+    /// itself; captured lines have none. `sd` is carried in addition to any SD-ELEMENT the
+    /// encoding calls for.
+    pub fn format(&self, priority: Priority, timestamp: &str, msgid: Option<&str>, sd: &[SdElement], msg: &str) -> String {
+        // TODO: Enforce newline?
+        // TODO: What if appname contains space?
+        let Formatter { hostname, appname, version, .. } = self;
+        let pri = priority.code();
+        let msgid = msgid.unwrap_or("-");
+        let structured_data = self.structured_data(sd).unwrap_or_else(|| "-".into());
+        format!("<{pri}>{version} {timestamp} {hostname} {appname} - {msgid} {structured_data} {msg}")
+    }
+
+    /// The STRUCTURED-DATA for a message carrying `sd`, or `None` if it has none.
+    pub fn structured_data(&self, sd: &[SdElement]) -> Option<String> {
+        let elements: String = self.encoding.sd_element().iter().chain(sd).map(SdElement::to_string).collect();
+        (!elements.is_empty()).then_some(elements)
+    }
+}
+
+/// Checks a HOSTNAME or APP-NAME value against RFC 5424, returning the value to send.
+/// Over-long values are cut down to `max_len` if `truncate` is set. Otherwise they're sent
+/// in full, unless `strict` is set, which makes them an error. With `strict`, characters
+/// outside of PRINTUSASCII (such as spaces) are an error too.
+pub fn header_field(field: &str, value: String, max_len: usize, truncate: bool, strict: bool) -> Result<String, String> {
+    if strict {
+        if let Some(c) = value.chars().find(|c| !c.is_ascii_graphic()) {
+            return Err(format!("The {field} `{value}` contains {c:?}, which RFC 5424 doesn't allow"));
+        }
+    }
+    if value.len() <= max_len {
+        return Ok(value);
+    }
+    if truncate {
+        let mut end = max_len;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        return Ok(value[..end].to_string());
+    }
+    if strict {
+        return Err(format!("The {field} `{value}` is {} bytes long, over the {max_len} RFC 5424 allows, \
+                            and truncation was turned off", value.len()));
+    }
+    Ok(value)
+}
+
+/// How many fractional digits of the second to include in timestamps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimestampPrecision {
+    Seconds,
+    Millis,
+    Micros,
+}
+
+/// Formats a time for the TIMESTAMP header field.
+pub fn timestamp(time: &DateTime<Utc>, precision: TimestampPrecision) -> String {
+    // Timestamp format per https://www.rfc-editor.org/rfc/rfc5424#section-6
+    // E.g: 2003-08-24T05:14:15.000003-07:00
+    let format = match precision {
+        TimestampPrecision::Seconds => "%Y-%m-%dT%H:%M:%S%:z",
+        TimestampPrecision::Millis => "%Y-%m-%dT%H:%M:%S%.3f%:z",
+        TimestampPrecision::Micros => "%Y-%m-%dT%H:%M:%S%.6f%:z",
+    };
+    time.format(format).to_string()
+}
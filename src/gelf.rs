@@ -0,0 +1,78 @@
+// Formatting and chunking messages as GELF (Graylog Extended Log Format) 1.1 documents.
+// See https://go2docs.graylog.org/current/getting_in_log_data/gelf.html
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+
+use crate::format::{Formatter, MsgEncoding, SdElement};
+use crate::priority::{self, Priority};
+
+const GELF_VERSION: &str = "1.1";
+pub const DEFAULT_GELF_PORT: u16 = 12201;
+
+/// Largest datagram we send; longer documents are split into chunks.
+const MAX_DATAGRAM_SIZE: usize = 8192;
+const CHUNK_MAGIC: [u8; 2] = [0x1e, 0x0f];
+const CHUNK_HEADER_SIZE: usize = CHUNK_MAGIC.len() + 8 + 1 + 1;
+const MAX_CHUNKS: usize = 128;
+
+/// Formats one message as a GELF JSON document. The syslog severity maps directly onto the
+/// GELF level, since GELF levels are syslog severities. Structured data params become
+/// additional fields. Only the formatter's hostname, appname and encoding apply.
+pub fn format(formatter: &Formatter, priority: Priority, timestamp: &DateTime<Utc>, msgid: Option<&str>,
+              sd: &[SdElement], msg: &str) -> String {
+    let Formatter { hostname, appname, encoding, .. } = formatter;
+    let seconds = timestamp.timestamp() as f64 + f64::from(timestamp.timestamp_subsec_micros()) / 1_000_000.0;
+    let mut document = serde_json::json!({
+        "version": GELF_VERSION,
+        "host": hostname,
+        "short_message": msg.trim_end_matches(['\r', '\n']),
+        "timestamp": seconds,
+        "level": priority.severity,
+        "_appname": appname,
+        "_facility": priority::facility_name(priority.facility),
+    });
+    if let Some(msgid) = msgid {
+        document["_msgid"] = msgid.into();
+    }
+    if *encoding == MsgEncoding::Base64 {
+        document["_encoding"] = "base64".into();
+    }
+    for (name, value) in sd.iter().flat_map(|element| &element.params) {
+        document[format!("_{}", name.replace('-', "_"))] = value.as_str().into();
+    }
+    document.to_string()
+}
+
+/// Splits a document into the datagrams to send over UDP: the document itself if it fits in
+/// one, otherwise GELF chunks sharing a message ID. Returns `None` if the document is too
+/// large even for the maximum number of chunks.
+pub fn datagrams(document: &[u8]) -> Option<Vec<Vec<u8>>> {
+    if document.len() <= MAX_DATAGRAM_SIZE {
+        return Some(vec![document.to_vec()]);
+    }
+    let chunks: Vec<&[u8]> = document.chunks(MAX_DATAGRAM_SIZE - CHUNK_HEADER_SIZE).collect();
+    if chunks.len() > MAX_CHUNKS {
+        return None;
+    }
+    let message_id = message_id();
+    Some(chunks.iter().enumerate().map(|(sequence, chunk)| {
+        let mut datagram = Vec::with_capacity(CHUNK_HEADER_SIZE + chunk.len());
+        datagram.extend_from_slice(&CHUNK_MAGIC);
+        datagram.extend_from_slice(&message_id);
+        datagram.push(sequence as u8);
+        datagram.push(chunks.len() as u8);
+        datagram.extend_from_slice(chunk);
+        datagram
+    }).collect())
+}
+
+/// A chunked message ID that's unique within this process and unlikely to collide with
+/// other senders'.
+fn message_id() -> [u8; 8] {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default();
+    let id = nanos ^ u64::from(std::process::id()).rotate_left(32) ^ COUNTER.fetch_add(1, Ordering::Relaxed);
+    id.to_be_bytes()
+}
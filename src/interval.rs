@@ -0,0 +1,28 @@
+// Deadlines for work the delivery thread does periodically, rather than once per line.
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct Interval {
+    period: Duration,
+    next: Instant,
+}
+
+impl Interval {
+    pub fn new(period: Duration) -> Interval {
+        Interval { period, next: Instant::now() + period }
+    }
+
+    /// Returns whether the deadline has passed, scheduling the next one if so.
+    pub fn elapsed(&mut self) -> bool {
+        let now = Instant::now();
+        if now < self.next {
+            return false;
+        }
+        self.next = now + self.period;
+        true
+    }
+
+    pub fn time_left(&self) -> Duration {
+        self.next.saturating_duration_since(Instant::now())
+    }
+}
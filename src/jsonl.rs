@@ -0,0 +1,39 @@
+// Printing each message as a JSON object with its fields picked apart, per `--emit-jsonl`.
+use crate::format::{Formatter, SdElement};
+use crate::priority::Priority;
+
+/// Everything that goes into one message, besides what the formatter supplies.
+pub struct Record<'a> {
+    pub priority: Priority,
+    pub timestamp: &'a str,
+    pub msgid: Option<&'a str>,
+    pub sd: &'a [SdElement],
+    pub msg: &'a str,
+    /// The command's output stream the line came from, or `None` for generated messages.
+    pub stream: Option<&'a str>,
+    /// The message's place in delivery order, from 1.
+    pub seq: u64,
+}
+
+impl Record<'_> {
+    /// Formats the record as one line of JSON, with the fields as they'd appear in the syslog
+    /// message. Nil fields are null.
+    pub fn to_json(&self, formatter: &Formatter) -> String {
+        serde_json::json!({
+            "pri": self.priority.code(),
+            "facility": self.priority.facility,
+            "severity": self.priority.severity,
+            "priority": self.priority.name(),
+            "version": formatter.version,
+            "timestamp": self.timestamp,
+            "hostname": formatter.hostname,
+            "appname": formatter.appname,
+            "procid": null,
+            "msgid": self.msgid,
+            "sd": formatter.structured_data(self.sd),
+            "msg": self.msg.strip_suffix('\n').unwrap_or(self.msg),
+            "stream": self.stream,
+            "seq": self.seq,
+        }).to_string()
+    }
+}
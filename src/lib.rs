@@ -0,0 +1,14 @@
+//! Formatting and delivering syslog messages over TLS, for embedding the wrapper's delivery in
+//! other programs. The `syslog-wrapper` binary is built on the same modules.
+//!
+//! `sender::Sender` delivers one formatted frame at a time, reconnecting as needed. Register a
+//! callback with `Sender::on_delivered` to follow delivery progress; see there for what a
+//! callback does and doesn't promise.
+pub mod format;
+#[cfg(feature = "gelf")]
+pub mod gelf;
+pub mod priority;
+pub mod sender;
+pub mod source_port;
+pub mod stats;
+pub mod trust;
@@ -0,0 +1,123 @@
+// Keeping a local, size-rotated copy of every message, per `--local-file`.
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+/// What gets written to the local file for each message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LocalFileFormat {
+    /// The RFC 5424 frame, exactly as sent to the primary server. With a GELF --transport, it's
+    /// the RFC 5424 frame the primary server would have been sent instead.
+    Wire,
+    /// Just the timestamp and the message.
+    Plain,
+}
+
+#[derive(Clone, Debug)]
+pub struct Rotation {
+    /// Rotate once the file would grow beyond this many bytes. Never rotate if `None`.
+    pub max_bytes: Option<u64>,
+    /// How many rotated files (`PATH.1`, `PATH.2`, ...) to keep besides the current one.
+    pub max_files: u32,
+}
+
+/// Writes lines to the local file on a thread of its own, so that a slow disk never holds up
+/// network delivery (and a slow network never holds up the file).
+pub struct LocalFile {
+    lines: Sender<String>,
+    writer: JoinHandle<()>,
+}
+
+impl LocalFile {
+    pub fn open(path: PathBuf, rotation: Rotation) -> LocalFile {
+        let (lines, receiver) = channel::<String>();
+        let writer = thread::spawn(move || {
+            let mut writer = RotatingWriter { path, rotation, file: None, size: 0, failing: false };
+            for line in receiver {
+                writer.write(&line);
+            }
+        });
+        LocalFile { lines, writer }
+    }
+
+    pub fn write(&self, line: String) {
+        // The writer only stops once we hang up, so this can't fail
+        let _ = self.lines.send(line);
+    }
+
+    /// Waits for everything written so far to reach the file.
+    pub fn close(self) {
+        drop(self.lines);
+        self.writer.join().unwrap();
+    }
+}
+
+struct RotatingWriter {
+    path: PathBuf,
+    rotation: Rotation,
+    file: Option<File>,
+    size: u64,
+    /// Whether the last write failed, so that a failing disk is only reported once.
+    failing: bool,
+}
+
+impl RotatingWriter {
+    /// Appends a line, reporting (but otherwise ignoring) any failure to do so.
+    fn write(&mut self, line: &str) {
+        match self.try_write(line) {
+            Ok(()) => self.failing = false,
+            Err(e) => {
+                if !self.failing {
+                    eprintln!("Unable to write to local file `{:?}`: {e}", self.path);
+                }
+                self.failing = true;
+                // Start over with a freshly opened file next time
+                self.file = None;
+            },
+        }
+    }
+
+    fn try_write(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + u64::from(!line.ends_with('\n'));
+        let needs_rotation = self.rotation.max_bytes.is_some_and(|max_bytes| self.size > 0 && self.size + len > max_bytes);
+        if needs_rotation {
+            self.file = None;
+            rotate(&self.path, self.rotation.max_files)?;
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+                self.size = file.metadata()?.len();
+                self.file.insert(file)
+            },
+        };
+        file.write_all(line.as_bytes())?;
+        if !line.ends_with('\n') {
+            file.write_all(b"\n")?;
+        }
+        self.size += len;
+        Ok(())
+    }
+}
+
+/// Shifts `PATH.N-1` to `PATH.N`, ..., `PATH` to `PATH.1`, discarding whatever falls off the end.
+fn rotate(path: &Path, max_files: u32) -> std::io::Result<()> {
+    let numbered = |n: u32| {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    };
+    if max_files == 0 {
+        return fs::remove_file(path);
+    }
+    for n in (1..max_files).rev() {
+        match fs::rename(numbered(n), numbered(n + 1)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {},
+        }
+    }
+    fs::rename(path, numbered(1))
+}
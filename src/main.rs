@@ -1,18 +1,53 @@
 use std::ffi::OsString;
-use std::fs::File;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::PathBuf;
-use std::process::{Command, Stdio, exit};
-use std::sync::mpsc::channel; // Multiple producer, single consumer channel
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, channel, RecvTimeoutError}; // Multiple producer, single consumer channel
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use clap::Parser; // Command line parsing
-use rustls::Certificate; // TLS and certificate parsing
-use chrono::Utc; // Formatting UTC time for syslog protocol
+use chrono::Utc;
+use clap::{CommandFactory, ErrorKind, Parser}; // Command line parsing
 
-const SYSLOG_PRIORITY: &str = "22"; // See RFC 5424 sec. 6.2.1
-const SYSLOG_VERSION: &str = "1"; // See RFC 5424 sec. 6.2.2
-const DEFAULT_SYSLOG_PORT: u16 = 6514;
+mod connection_events;
+mod dedup;
+#[cfg(unix)]
+mod detach;
+mod interval;
+#[cfg(feature = "gelf")]
+mod jsonl;
+mod local_file;
+mod pace;
+mod pidfile;
+mod privileges;
+mod ratelimit;
+mod redact;
+mod shed;
+#[cfg(unix)]
+mod signals;
+mod spawn_failure;
+#[cfg(unix)]
+mod strict_order;
+mod summary;
+#[cfg(feature = "test-server")]
+mod test_server;
+
+// Delivery itself lives in the library, for embedders
+#[cfg(feature = "gelf")]
+use syslog_wrapper::gelf;
+use syslog_wrapper::{format, priority, sender, source_port, stats, trust};
+
+use format::{MsgEncoding, SdElement, TimestampPrecision};
+use interval::Interval;
+use local_file::{LocalFile, LocalFileFormat};
+use priority::DEFAULT_PRIORITY;
+use ratelimit::{RateLimitKey, RateLimiter};
+use redact::Redactor;
+use stats::Stats;
+use sender::{Destination, Framing, Sender, Transport};
+use source_port::SourcePortRange;
 
 // https://docs.rs/clap/latest/clap/_derive/_cookbook/escaped_positional/index.html
 // https://docs.rs/retry/latest/retry/
@@ -36,39 +71,606 @@ Test suite items:
 struct Args {
     // TODO: Allow URI syntax
     /// Hostname (and optional :port, defaults to 6514) of the remote TCP syslog receiver.
-    #[clap(value_parser, env = "SYSLOG_SERVER")]
-    server: String,
+    #[clap(value_parser, env = "SYSLOG_SERVER", required_unless_present = "test-server")]
+    server: Option<String>,
+
+    /// Additional syslog server to deliver a copy of every message to, as HOST[:PORT]. May be
+    /// repeated. Append `,facility=NAME` and/or `,severity=NAME` to send that destination's copy
+    /// with a fixed facility/severity (e.g. `audit.example.com:6514,facility=local5,severity=info`).
+    /// Delivery carries on without an additional server that can't be reached (at startup, or
+    /// once retrying has given up), as long as the primary server can be.
+    #[clap(long, value_parser = Destination::parse)]
+    also_server: Vec<Destination>,
+
+    /// The priority to send messages with, as FACILITY[.SEVERITY] in the style of `logger -p`
+    /// (e.g. `local3.warning`, or `local3` for local3.info). Defaults to mail.info.
+    #[clap(long, value_parser = priority::parse_priority)]
+    priority: Option<priority::Priority>,
+
+    /// Deliver over this already-connected socket, inherited from the parent process (as with
+    /// systemd socket activation), instead of connecting to the server. TLS is still layered on
+    /// top, with the certificate checked against the server's name. The socket can't be
+    /// reconnected, so once it closes delivery has failed (see --echo-on-failure). Only applies
+    /// to the primary server. Unix only.
+    #[clap(long, value_parser = clap::value_parser!(i32).range(0..))]
+    use_fd: Option<i32>,
+
+    /// How messages are delivered. The default port depends on the transport: 6514 for
+    /// syslog over TLS, 12201 for GELF.
+    #[clap(long, value_enum, default_value_t = Transport::Tls)]
+    transport: Transport,
+
+    /// How messages are delimited on the wire: `octet-counting` (a length prefix, per RFC 5425),
+    /// or a trailing delimiter, per RFC 6587: `lf`, `nul` or `trailer:BYTE`. Many collectors
+    /// expect `lf`. Ignored by the GELF transports.
+    #[clap(long, value_parser = Framing::parse, default_value = "octet-counting")]
+    framing: Framing,
 
     /// The hostname to report on the syslog messages. Defaults to the actual system hostname.
     #[clap(value_parser, long, env = "SYSLOG_HOSTNAME")]
     hostname: Option<String>,
 
-    /// The app-name/program name to report on the syslog messages. Defaults to `command`, excluding any arguments.
+    /// The app-name/program name to report on the syslog messages. Defaults to `command` (or
+    /// --arg0, if given), excluding any arguments; see also
+    /// --appname-from-argv0-basename-without-extension.
     #[clap(value_parser, long, env = "SYSLOG_APPNAME")]
     appname: Option<String>,
 
-    /// Maximum number of times to retry consecutively before crashing
+    /// Derive the default app-name from just the file name of the command (or --arg0), without
+    /// its directory or extension, so that `C:\bin\myapp.exe` is reported as `myapp`. Both `/`
+    /// and `\` separate directories. A path with no file name, such as `/opt/myapp/`, is
+    /// reported whole. Ignored if --appname is given.
+    #[clap(long, value_parser)]
+    appname_from_argv0_basename_without_extension: bool,
+
+    /// Send hostnames longer than the 255 bytes RFC 5424 allows in full, rather than truncating
+    /// them, for collectors that accept them. Combined with --strict-rfc, an over-long hostname
+    /// is an error instead.
+    #[clap(long, value_parser)]
+    no_truncate_hostname: bool,
+
+    /// Send app-names longer than the 48 bytes RFC 5424 allows in full, rather than truncating
+    /// them, for collectors that accept them. Combined with --strict-rfc, an over-long app-name
+    /// is an error instead.
+    #[clap(long, value_parser)]
+    no_truncate_appname: bool,
+
+    /// Refuse to start with a hostname or app-name that would make the messages violate RFC 5424:
+    /// one containing spaces or other characters outside printable ASCII, or one that's too long
+    /// and isn't being truncated.
+    #[clap(long, value_parser)]
+    strict_rfc: bool,
+
+    /// Maximum number of times to retry consecutively before crashing, once at least one line
+    /// has been delivered
     #[clap(short, long, value_parser, default_value_t = 10)]
     max_retries: u8,
 
+    /// Maximum number of times to retry connecting before crashing, while nothing has been
+    /// delivered yet. Failing at startup usually means something is misconfigured, so this is kept
+    /// strict; a connection lost later on is more likely a network blip, covered by --max-retries.
+    #[clap(long, value_parser, default_value_t = 2)]
+    connect_retries_are_fatal_after: u8,
+
+    /// Minimum number of milliseconds between the starts of two connection attempts to the same
+    /// server, however short the backoff delay, so that a burst of reconnections can't overwhelm
+    /// a recovering server.
+    #[clap(long, value_parser, default_value_t = 0)]
+    min_reconnect_interval: u64,
+
+    /// Once a connection has stayed up for this many seconds, start the next outage's
+    /// reconnection backoff over from an immediate first attempt. Until then, each outage picks
+    /// up the backoff where the last one left it, so that a connection that keeps dropping gets
+    /// retried less and less often. Without this option, every outage starts over.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    backoff_reset_after: Option<u64>,
+
+    /// How precise timestamps are, both in the TIMESTAMP header field and with
+    /// --prefix-timestamp-in-msg.
+    #[clap(long, value_enum, default_value_t = TimestampPrecision::Micros)]
+    timestamp_precision: TimestampPrecision,
+
+    /// Also start each MSG with the message's timestamp, for collectors that replace the
+    /// TIMESTAMP header field with the time they received the message.
+    #[clap(long, value_parser)]
+    prefix_timestamp_in_msg: bool,
+
+    /// Start every MSG with TEXT, exactly as given (so include any trailing space), for
+    /// collectors that expect a fixed token there, as in `--msg-prefix 'type=app '`. It comes
+    /// ahead of everything else, --prefix-timestamp-in-msg included, and applies to the messages
+    /// the wrapper generates too. TEXT may not contain line breaks.
+    #[clap(long, value_parser = parse_msg_prefix, allow_hyphen_values = true)]
+    msg_prefix: Option<String>,
+
+    /// Replace every match of REGEX in each line with REPLACEMENT, as REGEX=REPLACEMENT, before
+    /// it's sent anywhere. The last `=` separates the two. REPLACEMENT may refer to capture
+    /// groups, as in `${1}`. May be repeated; rules apply in order.
+    #[clap(long, value_parser = redact::Rule::parse)]
+    redact: Vec<redact::Rule>,
+
+    /// Apply a bundle of common --redact rules, ahead of any custom ones: `pii` (email
+    /// addresses, card numbers, SSNs) or `secrets` (passwords, tokens and keys). May be repeated.
+    #[clap(long, value_enum)]
+    redact_preset: Vec<redact::Preset>,
+
+    /// How to carry each line in the message body. `base64` preserves the exact bytes of lines
+    /// that aren't valid UTF-8 or that collectors would mangle, and marks the message with an
+    /// `[encoding@32473 type="base64"]` structured data element so the collector can decode it.
+    #[clap(long, value_enum, default_value_t = MsgEncoding::Raw)]
+    encode_msg: MsgEncoding,
+
+    /// The VERSION field of the syslog header. Only versions whose header the wrapper knows how
+    /// to write are accepted, which is currently just 1, the one RFC 5424 defines.
+    #[clap(long, alias = "format-version", default_value_t = format::DEFAULT_SYSLOG_VERSION,
+           value_parser = parse_syslog_version)]
+    syslog_version: u16,
+
+    /// At startup, keep trying to connect to the server for up to this many seconds before giving
+    /// up, for when it may come up after the wrapper. Once a line has been delivered, --max-retries
+    /// applies.
+    #[clap(long, value_parser, default_value_t = 0)]
+    wait_for_server: u64,
+
+    /// Give up on a connection if the TLS handshake hasn't completed within this many seconds,
+    /// as with a server that accepts connections but then stalls. This counts as a failed
+    /// connection attempt, to be retried like any other.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    handshake_timeout: Option<u64>,
+
+    /// Once the command has exited, give delivery at most this many more seconds to finish, then
+    /// exit 127 regardless, reporting how many lines were never delivered, so that a connection
+    /// that keeps failing can't keep the wrapper (and so the job) alive indefinitely.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    post_exit_timeout: Option<u64>,
+
+    /// Connect from a local port within this range, as LOW-HIGH (e.g. `40000-40099`), for
+    /// networks whose firewalls only allow connections from certain ports. Ports already in use
+    /// are skipped; if none in the range is free, that counts as a failed connection attempt.
+    /// Doesn't apply to an inherited --use-fd socket. Unix only, apart from --transport gelf-udp.
+    #[clap(long, value_parser = SourcePortRange::parse)]
+    source_port_range: Option<SourcePortRange>,
+
+    /// If delivery fails for good (the server can't be reached, or retries run out), write every
+    /// line not yet delivered to stderr instead, including those the command printed before the
+    /// connection failed, and keep doing so until the command exits. The wrapper then exits 127.
+    #[clap(long, value_parser)]
+    echo_on_failure: bool,
+
+    /// How many seconds to reuse the addresses the server's name resolved to when reconnecting,
+    /// before resolving it again. A failed connection always discards them. 0 resolves the name
+    /// on every connection attempt.
+    #[clap(long, value_parser, default_value_t = 30)]
+    dns_cache_ttl: u64,
+
     /// Path to a file containing a PEM-encoded X509 certificate which will be added to the default trust store.
     #[clap(short, long, value_parser)]
     add_trusted_certificates: Option<PathBuf>,
 
+    /// PEM-encoded X509 certificate(s), given inline, which will be added to the default trust store.
+    /// Literal `\n` sequences (common when PEM is passed through an environment variable) are
+    /// treated as line breaks.
+    #[clap(long, value_parser, env = "SYSLOG_CA_PEM", hide_env_values = true, allow_hyphen_values = true)]
+    add_trusted_certificates_pem: Option<String>,
+
+    /// Coalesce consecutive identical lines: forward the line once, then a
+    /// `last message repeated N times` summary once a different line arrives.
+    #[clap(long, value_parser)]
+    dedup: bool,
+
+    /// With --dedup, also emit any pending repeat summary every this many seconds.
+    #[clap(long, value_parser, requires = "dedup")]
+    dedup_flush_interval: Option<u64>,
+
+    /// Push every line onto the wire as soon as it is written: flush the TLS stream after each
+    /// frame and disable Nagle's algorithm on the socket. This minimizes latency, but costs
+    /// throughput, since every line then travels in its own TLS record and TCP segment.
+    #[clap(long, value_parser)]
+    flush_on_each_line: bool,
+
+    /// Forward at most this many lines per second on average, dropping the rest. Bursts of up to
+    /// --rate-limit-burst lines are let through at once.
+    #[clap(long, value_parser = parse_rate)]
+    rate_limit: Option<f64>,
+
+    /// The largest burst of lines --rate-limit lets through at once. Defaults to one second's worth.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..), requires = "rate-limit")]
+    rate_limit_burst: Option<u32>,
+
+    /// Rate limit each distinct value of this field separately, so that a flood of one kind of
+    /// line doesn't starve the others: `stream` (stdout or stderr), or `word:N` for the Nth
+    /// whitespace-separated word of the line.
+    #[clap(long, value_parser = RateLimitKey::parse, requires = "rate-limit")]
+    rate_limit_key: Option<RateLimitKey>,
+
+    /// The most distinct --rate-limit-key values to track at once. Beyond that, the longest-idle
+    /// key is forgotten (and starts over with a full bucket if seen again).
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 1000, requires = "rate-limit-key")]
+    rate_limit_max_keys: u64,
+
+    /// Smooth out bursts by delivering at most this many lines per second, evenly spaced: lines
+    /// that arrive faster wait their turn, rather than being dropped as with --rate-limit. Once
+    /// the command has exited, whatever is still waiting is delivered without pacing.
+    #[clap(long, value_parser = parse_rate)]
+    pace_fps: Option<f64>,
+
+    /// Shed load while the command writes faster than lines can be delivered: once
+    /// --shed-high-water lines are waiting, forward only one line in --shed-keep-one-in, until
+    /// the backlog drains to --shed-low-water lines.
+    #[clap(long, value_parser)]
+    adaptive_shed: bool,
+
+    /// How many waiting lines start --adaptive-shed shedding.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 10000, requires = "adaptive-shed")]
+    shed_high_water: u64,
+
+    /// How few waiting lines stop --adaptive-shed shedding.
+    #[clap(long, value_parser, default_value_t = 1000, requires = "adaptive-shed")]
+    shed_low_water: u64,
+
+    /// While --adaptive-shed is shedding, forward one line in this many.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), default_value_t = 10, requires = "adaptive-shed")]
+    shed_keep_one_in: u64,
+
+    /// Every this many seconds, print a one-line summary to stderr of how many lines were
+    /// dropped (by --rate-limit or --adaptive-shed) in that period, broken down by reason.
+    /// Nothing is printed for periods without drops.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    drop_warn_interval: Option<u64>,
+
+    /// Also append every message to this local file, in addition to delivering it over the
+    /// network. Failing to write the file is reported, but doesn't stop delivery.
+    #[clap(long, value_parser)]
+    local_file: Option<PathBuf>,
+
+    /// What to write to --local-file: each RFC 5424 frame as sent to the primary server (still
+    /// RFC 5424 with a GELF --transport), or just the timestamp and message.
+    #[clap(long, value_enum, default_value_t = LocalFileFormat::Wire, requires = "local-file")]
+    local_file_format: LocalFileFormat,
+
+    /// Rotate --local-file once it would grow beyond this many bytes.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..), requires = "local-file")]
+    local_file_max_bytes: Option<u64>,
+
+    /// How many rotated copies of --local-file (`PATH.1`, `PATH.2`, ...) to keep.
+    #[clap(long, value_parser, default_value_t = 5, requires = "local-file")]
+    local_file_max_files: u32,
+
+    /// Working directory to run the command in. Defaults to the current directory.
+    #[clap(long, value_parser)]
+    workdir: Option<PathBuf>,
+
+    /// Attach the values of these environment variables (as the command sees them), along with
+    /// the command line, to the first message only, as `[env@32473 KEY="VALUE" ...]` and
+    /// `[command@32473 line="..."]` structured data, as a record of how the command was run.
+    /// Variables that aren't set are left out, and the env element with them if none are.
+    #[clap(long, value_parser = parse_sd_name, value_delimiter = ',')]
+    capture_env: Vec<String>,
+
+    /// Set an environment variable for the command, as KEY=VALUE. May be repeated.
+    #[clap(long = "env", value_parser = parse_env_var)]
+    child_env: Vec<(String, String)>,
+
+    /// Start the command with an empty environment, apart from any --env variables.
+    #[clap(long, value_parser)]
+    env_clear: bool,
+
+    /// Read the command's stdout and stderr from a single thread, as each becomes readable, so
+    /// that their lines are forwarded in the order the command wrote them, rather than racing
+    /// between a thread per stream. Lines written too close together for the wrapper to tell
+    /// apart may still come out of order. Unix only.
+    #[clap(long, value_parser)]
+    strict_order: bool,
+
+    /// Ask the command not to use color, by setting NO_COLOR=1 and TERM=dumb in its environment
+    /// (--env takes precedence). This is best-effort: it only works for programs that follow
+    /// those conventions, but for them it keeps escape codes out of the log at the source.
+    #[clap(long, value_parser)]
+    child_no_color: bool,
+
+    /// Also forward lines written to the wrapper's own stdin (as stream `stdin`), for instance to
+    /// add notes from an operator to the command's logs. The command's stdin is then /dev/null.
+    /// Lines still arriving once the command has exited are not forwarded.
+    #[clap(long, value_parser)]
+    read_stdin_also: bool,
+
+    /// Run the command as this user (name or uid), and with their primary group unless --group
+    /// is given. The wrapper itself keeps its own privileges, e.g. to read root-owned certificates.
+    /// Unix only.
+    #[clap(long, value_parser = privileges::parse_user)]
+    user: Option<privileges::User>,
+
+    /// Run the command as this group (name or gid). Unix only.
+    #[clap(long, value_parser = privileges::parse_group)]
+    group: Option<u32>,
+
+    /// The argv[0] the command sees, instead of the path it was run by. Useful for multi-call
+    /// binaries like busybox. Unix only.
+    #[clap(long, value_parser)]
+    arg0: Option<OsString>,
+
+    /// Write the command's PID to this file once it has started, and remove the file when the
+    /// wrapper exits. If the wrapper itself is killed uncleanly the file is left behind, so
+    /// consumers should check that the PID is still alive before trusting it.
+    #[clap(long, value_parser)]
+    pidfile: Option<PathBuf>,
+
+    /// Check the configuration (parse every certificate, destination and option value) and then
+    /// exit, without connecting or running the command. The same checks always run before the
+    /// command is started, so a bad configuration never leaves it running without delivery.
+    #[clap(long, value_parser)]
+    validate_config: bool,
+
+    /// Once the command has exited, send one last message (MSGID SUMMARY) describing the run as
+    /// structured data: lines and bytes read, lines forwarded, duration, reconnections,
+    /// duplicates suppressed, redactions, drops by reason and the command's exit code. Lines
+    /// forwarded are those the primary server was sent. Nothing is sent if --post-exit-timeout
+    /// gives up on delivery, as delivery is what's stuck.
+    #[clap(long, value_parser)]
+    run_summary: bool,
+
+    /// Deliver a message (MSGID `CONN_DOWN`) whenever a connection to a server is lost, and
+    /// another (`CONN_UP`) once it's re-established, with `[conn@32473 ...]` structured data
+    /// giving the server's address, and the error or the downtime and number of attempts. These
+    /// go to every server, behind whatever was already waiting, so `CONN_DOWN` reaches the server
+    /// that went down only once it's back up (if it ever is).
+    #[clap(long, value_parser)]
+    connection_events: bool,
+
+    /// Attach this SD-ELEMENT to every message, as `SD-ID [NAME=VALUE ...]` (e.g.
+    /// `--sd 'meta@32473 env=prod note="two words"'`). Quote a value to include spaces, with
+    /// `\"` and `\\` for `"` and `\`. May be repeated, with a different SD-ID each time; the
+    /// elements appear in the order given. SD-IDs and names must be printable ASCII without
+    /// spaces, `=`, `]` or `"`.
+    #[clap(long = "sd", value_parser = SdElement::parse)]
+    static_sd: Vec<SdElement>,
+
+    /// Number every message sent, in delivery order and starting from 1, with a
+    /// `[seq@32473 n="N"]` structured data element, so that the collector can detect gaps. A
+    /// message re-sent after a reconnect keeps its number, so duplicates can be detected too.
+    #[clap(long, value_parser)]
+    sequence_sd: bool,
+
+    /// Also print every message to stdout as a JSON object (one per line) with its fields picked
+    /// apart: pri, facility, severity, priority (the PRI spelled out, as in `mail.info`),
+    /// version, timestamp, hostname, appname, procid, msgid, sd, msg, plus the stream the line
+    /// came from and its place in delivery order (seq). The fields are those sent to the primary
+    /// server. Handy for local debugging.
+    #[cfg(feature = "gelf")]
+    #[clap(long, value_parser)]
+    emit_jsonl: bool,
+
+    /// Deliver MESSAGE (with MSGID `SIGNAL`) whenever the wrapper receives SIGNAL, as in
+    /// `SIGUSR2=manual checkpoint`, so that operators can annotate the log stream. May be
+    /// repeated. Signals that can't be handled, such as SIGKILL and SIGSTOP, are refused. A
+    /// hooked signal no longer has its usual effect on the wrapper, so hooking SIGTERM or SIGINT
+    /// means they don't terminate it.
+    #[cfg(unix)]
+    #[clap(long, value_parser = signals::SignalMessage::parse)]
+    emit_on_signal: Vec<signals::SignalMessage>,
+
+    /// Don't wait around for the command: the wrapper forks a detached copy of itself (in a new
+    /// session) that runs the command and delivers its output, and the original wrapper exits 0
+    /// as soon as the connection to the server is up. If that fails, the original wrapper exits
+    /// with the detached copy's exit code instead. The detached copy keeps the original stdin,
+    /// stdout and stderr for its own diagnostics, and is reparented to init (or the nearest
+    /// subreaper) once the original exits. Unix only.
+    #[clap(long, value_parser)]
+    no_wait_child: bool,
+
     /// The actual command to run, and the standard output and standard error
     /// of which will be captured.
-    #[clap(last = true, value_parser, required = true)]
+    #[clap(last = true, value_parser, required_unless_present_any = &["validate-config", "test-server"])]
     command: Vec<OsString>,
+
+    /// Instead of running a command, receive one connection on this port as a TLS syslog server,
+    /// printing whatever arrives. The server's certificate is signed by the CA in `certs/ca.crt`.
+    /// Only in builds with the `test-server` feature, which the tests turn on.
+    #[clap(long, value_parser, hide = true)]
+    test_server: Option<u16>,
 }
 
+/// The SD-IDs of the elements the wrapper adds itself, which --sd can't repeat.
+const GENERATED_SD_IDS: [&str; 6] = [
+    "encoding@32473", "seq@32473", "env@32473", "command@32473", "summary@32473", "conn@32473",
+];
+
 #[derive(Debug)]
 enum DeliverValue {
-    Line(String),
-    Eof(),
+    /// A line, and the name of the stream it was read from: `stdout` or `stderr` for the
+    /// command's, or `stdin` for the wrapper's own, with --read-stdin-also.
+    Line(String, &'static str),
+    /// A message about a signal, and its MSGID: one the wrapper received, with
+    /// --emit-on-signal, or the one that killed the command.
+    #[cfg(unix)]
+    Signal(&'static str, String),
+    /// A delivery connection was lost or re-established, with --connection-events.
+    Connection(sender::ConnectionEvent),
+    /// The command's output has ended, along with its exit code, if it has one.
+    Eof(Option<i32>),
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate.is_finite() => Ok(rate),
+        _ => Err(format!("`{value}` is not a positive number of lines per second")),
+    }
+}
+
+fn parse_msg_prefix(value: &str) -> Result<String, String> {
+    match value.contains(['\n', '\r']) {
+        true => Err("the prefix must not contain line breaks".to_string()),
+        false => Ok(value.to_string()),
+    }
+}
+
+fn parse_syslog_version(value: &str) -> Result<u16, String> {
+    match value.parse() {
+        Ok(version) if format::SYSLOG_VERSIONS.contains(&version) => Ok(version),
+        _ => Err(format!("only version {} can be written",
+                         format::SYSLOG_VERSIONS.iter().map(u16::to_string).collect::<Vec<_>>().join(", "))),
+    }
+}
+
+fn parse_env_var(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() && !key.contains('\0') && !value.contains('\0') =>
+            Ok((key.into(), value.into())),
+        _ => Err(format!("`{value}` is not of the form KEY=VALUE")),
+    }
+}
+
+/// Reads lines from one of the command's output streams until it closes, forwarding each one
+/// (redacted) to the delivery thread as MSG text.
+fn forward_lines(reader: impl Read, sender: mpsc::Sender<DeliverValue>, encoding: MsgEncoding, stream: &'static str,
+                 redactor: Arc<Redactor>, stats: Arc<Stats>) {
+    let mut reader = BufReader::new(reader);
+    loop {
+        let mut line = Vec::new();
+        let len = reader.read_until(b'\n', &mut line)
+            .unwrap_or_else(|e| panic!("error reading next line from subcommand's {stream}: {e}"));
+        if len == 0 {
+            break;
+        }
+        if !forward_line(line, stream, &sender, encoding, &redactor, &stats) {
+            // Delivery is over, which only happens to a reader that isn't waited for (stdin)
+            break;
+        }
+    }
+}
+
+/// Forwards one line read from `stream` to the delivery thread, returning whether delivery is
+/// still accepting lines.
+fn forward_line(line: Vec<u8>, stream: &'static str, sender: &mpsc::Sender<DeliverValue>, encoding: MsgEncoding,
+                redactor: &Redactor, stats: &Stats) -> bool {
+    stats.lines_read.fetch_add(1, Ordering::Relaxed);
+    stats.bytes_read.fetch_add(line.len() as u64, Ordering::Relaxed);
+    // TODO: Possibly have a pass-through/tee mode that also echoes?
+    let line = if redactor.is_empty() { line } else { redactor.redact(line, stats) };
+    let line = encoding.encode(line)
+        .unwrap_or_else(|e| panic!("error reading next line from subcommand's {stream}: {e}"));
+    stats.queue_depth.fetch_add(1, Ordering::Relaxed);
+    sender.send(DeliverValue::Line(line, stream)).is_ok()
+}
+
+/// What --child-no-color sets in the command's environment, ahead of any --env.
+const NO_COLOR_ENV: [(&str, &str); 2] = [("NO_COLOR", "1"), ("TERM", "dumb")];
+
+/// The value the command sees for an environment variable: the one given with --env, or else
+/// the one --child-no-color sets, or else the wrapper's own, unless --env-clear removed it.
+fn command_env_var(args: &Args, key: &str) -> Option<OsString> {
+    if let Some((_, value)) = args.child_env.iter().rev().find(|(name, _)| name == key) {
+        return Some(value.into());
+    }
+    match NO_COLOR_ENV.iter().find(|(name, _)| *name == key) {
+        Some((_, value)) if args.child_no_color => Some(value.into()),
+        _ if args.env_clear => None,
+        _ => std::env::var_os(key),
+    }
+}
+
+/// The one-time `--capture-env` structured data: the listed variables the command was started
+/// with, if any are set, and the command line.
+fn captured_env(args: &Args) -> Vec<SdElement> {
+    let mut env = SdElement::new("env@32473");
+    for key in &args.capture_env {
+        if let Some(value) = command_env_var(args, key) {
+            env = env.param(key, value.to_string_lossy());
+        }
+    }
+    let command_line: Vec<_> = args.command.iter().map(|arg| arg.to_string_lossy()).collect();
+    let command = SdElement::new("command@32473").param("line", command_line.join(" "));
+    (!env.params.is_empty()).then_some(env).into_iter().chain([command]).collect()
+}
+
+fn parse_sd_name(value: &str) -> Result<String, String> {
+    format::check_sd_name(value).map(|()| value.to_string())
+}
+
+/// Exits the wrapper, first cleaning up anything that shouldn't outlive it.
+fn exit(code: i32) -> ! {
+    pidfile::remove();
+    std::process::exit(code)
+}
+
+/// The exit code the wrapper passes on for the command. A command killed by a signal is
+/// reported the way a shell would, as 128 plus the signal number.
+fn exit_code(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(status) {
+        return Some(128 + signal);
+    }
+    status.code()
+}
+
+/// clap hands us an exported-but-blank environment variable as a present, empty value,
+/// which would produce an invalid header field. Treat it as though it were unset.
+fn non_empty(value: Option<String>) -> Option<String> {
+    value.filter(|value| !value.is_empty())
+}
+
+/// The file name in `path`, minus any extension: `myapp` for `C:\bin\myapp.exe` or
+/// `/usr/bin/myapp.sh`. A leading dot, as in `.hidden`, doesn't start an extension.
+fn basename_without_extension(path: &str) -> &str {
+    let basename = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    match basename.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => basename,
+    }
+}
+
+/// Names the wrapper option the command starts with, if it does. No command is named like a
+/// flag, so that's a wrapper option put after `--` by mistake, as in `-- --hostname h cmd`.
+/// Positional arguments count under their names too, as in `-- --server logs.example.com cmd`.
+fn misplaced_option(command: &[OsString]) -> Option<String> {
+    let first = command.first()?.to_str()?;
+    let flag = first.split_once('=').map_or(first, |(flag, _)| flag);
+    let is_option = |arg: &clap::Arg| match flag.strip_prefix("--") {
+        Some(long) => arg.get_long() == Some(long) || (arg.is_positional() && arg.get_id() == long),
+        None => flag.len() == 2 && flag.starts_with('-') && arg.get_short() == flag[1..].chars().next(),
+    };
+    Args::command().get_arguments().any(is_option).then(|| flag.to_string())
+}
+
+fn misplaced_option_error(flag: &str) -> ! {
+    Args::command().error(ErrorKind::UnknownArgument, format!(
+        "`{flag}` was given as the command to run, since everything after `--` is the command \
+         and its arguments; did you mean to put {flag} before --?"
+    )).exit()
 }
 
 fn main() {
-    let mut args = Args::parse();
+    let mut args = Args::try_parse().unwrap_or_else(|error| {
+        // With the server put after `--` too, it's reported missing before the command is looked at
+        if error.kind() == ErrorKind::MissingRequiredArgument {
+            let command: Vec<OsString> = std::env::args_os().skip_while(|arg| arg != "--").skip(1).collect();
+            if let Some(flag) = misplaced_option(&command) {
+                misplaced_option_error(&flag);
+            }
+        }
+        error.exit()
+    });
+
+    if let Some(port) = args.test_server {
+        #[cfg(feature = "test-server")]
+        test_server::run(port);
+        #[cfg(not(feature = "test-server"))]
+        Args::command().error(ErrorKind::ArgumentConflict, format!(
+            "--test-server {port} is only available in builds with the `test-server` feature"
+        )).exit();
+    }
+
+    if let Some(flag) = misplaced_option(&args.command) {
+        misplaced_option_error(&flag);
+    }
+
+    args.hostname = non_empty(args.hostname);
+    args.appname = non_empty(args.appname);
+    let server = args.server.take().expect("The command line parser failed.");
+    if server.is_empty() {
+        Args::command()
+            .error(ErrorKind::MissingRequiredArgument, "The syslog server must not be empty (is SYSLOG_SERVER set to a blank value?)")
+            .exit();
+    }
 
     // TODO: Drop into builder mode so these don't have to be ugly Optionals.
     // See https://docs.rs/clap/latest/clap/_derive/index.html#mixing-builder-and-derive-apis
@@ -76,18 +678,131 @@ fn main() {
         args.hostname = Some(gethostname::gethostname().to_string_lossy().to_string());
     }
 
-    if args.appname.is_none() {
-        args.appname = Some(args.command[0].to_string_lossy().to_string());
+    // With --validate-config there may be no command to name the app after
+    if let (None, Some(command_name)) = (&args.appname, args.arg0.as_ref().or(args.command.first())) {
+        let command_name = command_name.to_string_lossy();
+        // An empty APP-NAME isn't valid, so a path ending in a separator keeps the whole path
+        let appname = match (args.appname_from_argv0_basename_without_extension, &*command_name) {
+            (_, "") => "-",
+            (true, path) => Some(basename_without_extension(path)).filter(|name| !name.is_empty()).unwrap_or(path),
+            (false, path) => path,
+        };
+        args.appname = Some(appname.to_string());
     }
 
-    let (host, port): (String, u16) = match args.server.split_once(":") {
-        Some((host, port_str)) => (host.into(), port_str.parse().unwrap()),
-        None => (args.server, DEFAULT_SYSLOG_PORT),
-    };
+    let check_header_field = |field, value: Option<String>, max_len, truncate| value.map(|value| {
+        format::header_field(field, value, max_len, truncate, args.strict_rfc)
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::InvalidValue, e).exit())
+    });
+    args.hostname = check_header_field("hostname", args.hostname, format::MAX_HOSTNAME_LEN, !args.no_truncate_hostname);
+    args.appname = check_header_field("app-name", args.appname, format::MAX_APPNAME_LEN, !args.no_truncate_appname);
+
+    let mut primary_destination = Destination::parse(&server).unwrap_or_else(|e| {
+        Args::command().error(ErrorKind::InvalidValue, format!("Invalid syslog server: {e}")).exit()
+    });
+    primary_destination.inherited_fd = args.use_fd;
+    let destinations: Vec<Destination> = std::iter::once(primary_destination)
+        .chain(args.also_server.iter().cloned())
+        .collect();
+
+    // Anything that could still be wrong with the configuration is caught here, before the
+    // command has started
+    let root_store = trust::build_root_store(
+        args.add_trusted_certificates.as_deref(),
+        args.add_trusted_certificates_pem.as_deref(),
+    ).unwrap_or_else(|e| Args::command().error(ErrorKind::InvalidValue, e).exit());
+    for destination in &destinations {
+        if let Err(e) = destination.server_name() {
+            Args::command().error(ErrorKind::InvalidValue, e).exit();
+        }
+    }
+    if args.msg_prefix.is_some() && args.encode_msg == MsgEncoding::Base64 {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--msg-prefix can't be used with --encode-msg base64")
+            .exit();
+    }
+    if args.prefix_timestamp_in_msg && args.encode_msg == MsgEncoding::Base64 {
+        Args::command()
+            .error(ErrorKind::ArgumentConflict, "--prefix-timestamp-in-msg can't be used with --encode-msg base64")
+            .exit();
+    }
+    for (i, element) in args.static_sd.iter().enumerate() {
+        if GENERATED_SD_IDS.contains(&element.id.as_str()) || args.static_sd[..i].iter().any(|e| e.id == element.id) {
+            Args::command()
+                .error(ErrorKind::InvalidValue, format!("The SD-ID `{}` is used more than once (see --sd)", element.id))
+                .exit();
+        }
+    }
+    if args.shed_low_water >= args.shed_high_water {
+        Args::command()
+            .error(ErrorKind::InvalidValue, "--shed-low-water must be below --shed-high-water")
+            .exit();
+    }
+    if args.validate_config {
+        println!("The configuration is valid.");
+        exit(0);
+    }
+
+
+    // Nothing has been started yet, so this is the time to fork, while there's just the one thread
+    #[cfg(unix)]
+    let detached = args.no_wait_child.then(detach::detach);
+    #[cfg(not(unix))]
+    if args.no_wait_child {
+        Args::command().error(ErrorKind::ArgumentConflict, "--no-wait-child is only supported on Unix").exit();
+    }
 
     let command_name = args.command[0].clone();
-    let spawn_result = Command::new(command_name.clone())
-        .args(&args.command[1..])
+    let mut command = Command::new(command_name.clone());
+    command.args(&args.command[1..]);
+    if let Some(workdir) = &args.workdir {
+        command.current_dir(workdir);
+    }
+    if args.env_clear {
+        command.env_clear();
+    }
+    if args.child_no_color {
+        command.envs(NO_COLOR_ENV);
+    }
+    command.envs(args.child_env.iter().map(|(key, value)| (key, value)));
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        if let Some(gid) = args.group.or(args.user.map(|user| user.gid)) {
+            command.gid(gid);
+        }
+        if let Some(user) = args.user {
+            command.uid(user.uid);
+        }
+        if let Some(arg0) = &args.arg0 {
+            command.arg0(arg0);
+        }
+    }
+    // The inherited socket is for us, not the command
+    #[cfg(unix)]
+    if let Some(fd) = args.use_fd {
+        unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+    }
+    #[cfg(not(unix))]
+    if args.arg0.is_some() {
+        Args::command().error(ErrorKind::ArgumentConflict, "--arg0 is only supported on Unix").exit();
+    }
+    #[cfg(not(unix))]
+    if args.strict_order {
+        Args::command().error(ErrorKind::ArgumentConflict, "--strict-order is only supported on Unix").exit();
+    }
+    if args.read_stdin_also {
+        command.stdin(Stdio::null());
+    }
+    // TODO: Consider using sync_channel here with a bound, if we want to apply backpressure to the subprocess.
+    let (sender, receiver) = channel();
+
+    // Before the spawn, so that no signal sent once the command is running goes unannounced
+    #[cfg(unix)]
+    signals::emit_on_signals(std::mem::take(&mut args.emit_on_signal), sender.clone());
+
+    let started = Instant::now();
+    let spawn_result = command
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn();
@@ -96,119 +811,347 @@ fn main() {
         Ok(child) => child,
         Err(error) => {
             eprintln!("An error occurred launching {command_name:?}: {error}");
+            let path = command_env_var(&args, "PATH");
+            let lookup = spawn_failure::Lookup {
+                command: &command_name,
+                workdir: args.workdir.as_deref(),
+                path: path.as_deref(),
+            };
+            if let Some(explanation) = spawn_failure::explain(&lookup, &error) {
+                eprintln!("{explanation}");
+            }
             exit(40);
         },
     };
 
-    let mut stdout_reader = BufReader::new(child_process.stdout.take().unwrap());
-    let mut stderr_reader = BufReader::new(child_process.stderr.take().unwrap());
+    if let Some(path) = &args.pidfile {
+        pidfile::write(path, child_process.id());
+    }
 
-    // TODO: Consider using sync_channel here with a bound, if we want to apply backpressure to the subprocess.
-    let (sender, receiver) = channel();
+    let stdout = child_process.stdout.take().unwrap();
+    let stderr = child_process.stderr.take().unwrap();
 
-    let stdout_sender = sender.clone();
-    let stdout_handler = thread::spawn(move || loop {
-        let mut line = String::new();
-        let len = stdout_reader.read_line(&mut line).expect("error reading next line from subcommand's stdout");
-        if len == 0 {
-            break;
-        }
-        // TODO: Possibly have a pass-through/tee mode that also echoes?
-        // println!("stdout line is {len} bytes long");
-        stdout_sender
-            .send(DeliverValue::Line(line))
-            .expect("receiver hung up :(");
-    });
+    let stats = Arc::new(Stats::default());
+    let redactor = Arc::new(Redactor::new(&args.redact_preset, std::mem::take(&mut args.redact)));
 
-    let stderr_sender = sender.clone();
-    let stderr_handler = thread::spawn(move || loop {
-        let mut line = String::new();
-        let len = stderr_reader.read_line(&mut line).expect("error reading next line from subcommand's stderr");
-        if len == 0 {
-            break;
+    let encoding = args.encode_msg;
+    let mut readers = vec![];
+    if args.strict_order {
+        // Only reachable on Unix
+        #[cfg(unix)]
+        {
+            let (both_sender, both_redactor, both_stats) = (sender.clone(), redactor.clone(), stats.clone());
+            readers.push(thread::spawn(move || {
+                strict_order::read_both(stdout, stderr, |line, stream|
+                    forward_line(line, stream, &both_sender, encoding, &both_redactor, &both_stats))
+                    .unwrap_or_else(|e| panic!("error reading next line from subcommand's output: {e}"));
+            }));
         }
-        // TODO: Possibly have a pass-through/tee mode that also echoes?
-        // eprintln!("stderr line is {len} bytes long");
-        stderr_sender
-            .send(DeliverValue::Line(line))
-            .expect("receiver hung up :(");
-    });
+    } else {
+        let stdout_sender = sender.clone();
+        let (stdout_redactor, stdout_stats) = (redactor.clone(), stats.clone());
+        readers.push(thread::spawn(move ||
+            forward_lines(stdout, stdout_sender, encoding, "stdout", stdout_redactor, stdout_stats)));
 
-    let delivery = thread::spawn(move || {
-        let mut socket = std::net::TcpStream::connect((host.clone(), port)).unwrap_or_else(|e| {
-            eprintln!("Unable to connect to `{host}:{port}`: {e}");
-            exit(127);
-        });
-
-        let mut root_store = rustls::RootCertStore::empty();
-
-        if let Some(trusted_certificates_file) = args.add_trusted_certificates {
-            let cert_file = File::open(trusted_certificates_file.clone())
-                .unwrap_or_else(|e| 
-                    panic!("Could not open trusted certificate file `{trusted_certificates_file:?}`: {e}.")
-                );
-            let mut cert_file_reader = std::io::BufReader::new(cert_file);
-            // TODO: Would be easy to allow multiple certificates here.
-            let custom_cert = match rustls_pemfile::read_one(&mut cert_file_reader) {
-                Ok(Some(rustls_pemfile::Item::X509Certificate(cert_data))) => cert_data,
-                Ok(_) => panic!("The trusted certificate file did not contain a parseable certificate."),
-                Err(e) => panic!("Could not parse trusted certificate: {e}"),
-            };
-
-            root_store
-                .add(&Certificate(custom_cert))
-                .expect("Could not add trusted certificate.");
-        }
+        let stderr_sender = sender.clone();
+        let (stderr_redactor, stderr_stats) = (redactor.clone(), stats.clone());
+        readers.push(thread::spawn(move ||
+            forward_lines(stderr, stderr_sender, encoding, "stderr", stderr_redactor, stderr_stats)));
+    }
 
-        root_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
-            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
-                ta.subject,
-                ta.spki,
-                ta.name_constraints,
-            )
-        }));
+    // Not joined: stdin may well stay open long after the command has gone
+    if args.read_stdin_also {
+        let (stdin_sender, stdin_redactor, stdin_stats) = (sender.clone(), redactor.clone(), stats.clone());
+        thread::spawn(move ||
+            forward_lines(std::io::stdin(), stdin_sender, encoding, "stdin", stdin_redactor, stdin_stats));
+    }
 
+    let mut startup_sd = if args.capture_env.is_empty() { vec![] } else { captured_env(&args) };
+    let delivery_stats = stats.clone();
+    // Set once the command has exited, so that --pace-fps stops holding lines back
+    let draining = Arc::new(AtomicBool::new(false));
+    let main_draining = draining.clone();
+    let event_sender = sender.clone();
+    let delivery = thread::spawn(move || {
         let config = rustls::ClientConfig::builder()
             .with_safe_defaults()
             .with_root_certificates(root_store)
             .with_no_client_auth();
 
-        let arc = std::sync::Arc::new(config);
-        let server_name = host.as_str().try_into().unwrap();
-        let mut client = rustls::ClientConnection::new(arc, server_name).unwrap();
-        let mut stream = rustls::Stream::new(&mut client, &mut socket);
+        let config = Arc::new(config);
+        let sender_options = sender::Options {
+            framing: args.framing,
+            flush_on_each_line: args.flush_on_each_line,
+            max_retries: args.max_retries,
+            startup_retries: args.connect_retries_are_fatal_after,
+            min_reconnect_interval: Duration::from_millis(args.min_reconnect_interval),
+            wait_for_server: Duration::from_secs(args.wait_for_server),
+            dns_cache_ttl: Duration::from_secs(args.dns_cache_ttl),
+            handshake_timeout: args.handshake_timeout.map(Duration::from_secs),
+            backoff_reset_after: args.backoff_reset_after.map(Duration::from_secs),
+            source_ports: args.source_port_range,
+        };
+        let primary_override = destinations[0].priority_override;
+        let connect = |destination| Sender::connect(destination, args.transport, config.clone(),
+                                                    sender_options.clone(), stats.clone());
+        let mut destinations = destinations.into_iter();
+        // Only the primary server is essential: an --also-server that can't be reached is left out
+        let connected = connect(destinations.next().expect("There's always a primary server.")).map(|primary| {
+            let mut senders = vec![primary];
+            for destination in destinations {
+                match connect(destination) {
+                    Ok(sender) => senders.push(sender),
+                    Err(_) => eprintln!("Carrying on without that --also-server."),
+                }
+            }
+            senders
+        });
+        // Once delivery has failed with --echo-on-failure, lines go to stderr instead
+        let mut gave_up = false;
+        let mut senders = match connected {
+            Ok(mut senders) => {
+                if args.connection_events {
+                    for sender in senders.iter_mut() {
+                        let events = event_sender.clone();
+                        sender.on_connection_event(move |event| {
+                            // Delivery may be over by the time the last connection is lost
+                            let _ = events.send(DeliverValue::Connection(event));
+                        });
+                    }
+                }
+                #[cfg(unix)]
+                if let Some(detached) = detached {
+                    detached.delivery_established();
+                }
+                senders
+            },
+            Err(_) if args.echo_on_failure => {
+                gave_up = true;
+                vec![]
+            },
+            Err(_) => exit(127),
+        };
+
+        let formatter = format::Formatter {
+            hostname: args.hostname.expect("The command line parser failed."),
+            appname: args.appname.expect("The command line parser failed."),
+            encoding: args.encode_msg,
+            version: args.syslog_version,
+        };
+        let local_file = args.local_file.map(|path| LocalFile::open(path, local_file::Rotation {
+            max_bytes: args.local_file_max_bytes,
+            max_files: args.local_file_max_files,
+        }));
+        let base_priority = args.priority.unwrap_or(DEFAULT_PRIORITY);
+        let mut sequence: u64 = 0;
+        // Echoed lines are for reading locally, so they're written as the command wrote them
+        let echo = |str: &str| {
+            let _ = std::io::stderr().write_all(&args.encode_msg.decode(str));
+        };
+        // `stream` is that of the line `str` was read from; generated messages have none
+        let mut deliver = |msgid: Option<&str>, sd: &[SdElement], stream: Option<&str>, str: &str| {
+            sequence += 1;
+            let sequence_element = args.sequence_sd.then(|| SdElement::new("seq@32473").param("n", sequence));
+            let sd: Vec<SdElement> = sequence_element.into_iter()
+                .chain(std::mem::take(&mut startup_sd))
+                .chain(args.static_sd.iter().cloned())
+                .chain(sd.iter().cloned())
+                .collect();
+            let sd = sd.as_slice();
+            // TODO: Produce timestamp on sending thread in case this one is behind during a retry?
+            // Every destination gets the same timestamp, so the copies can be correlated.
+            let now = Utc::now();
+            let timestamp = format::timestamp(&now, args.timestamp_precision);
+            let prefixed;
+            let msg = if args.prefix_timestamp_in_msg || args.msg_prefix.is_some() {
+                // --msg-prefix always comes first, for collectors that parse the MSG by position
+                let msg_prefix = args.msg_prefix.as_deref().unwrap_or_default();
+                let timestamp_prefix = if args.prefix_timestamp_in_msg { format!("{timestamp} ") } else { String::new() };
+                prefixed = format!("{msg_prefix}{timestamp_prefix}{str}");
+                &prefixed
+            } else {
+                str
+            };
+            if let Some(local_file) = &local_file {
+                local_file.write(match args.local_file_format {
+                    LocalFileFormat::Wire =>
+                        formatter.format(primary_override.apply(base_priority), &timestamp, msgid, sd, msg),
+                    LocalFileFormat::Plain => format!("{timestamp} {str}"),
+                });
+            }
+            #[cfg(feature = "gelf")]
+            if args.emit_jsonl {
+                let priority = primary_override.apply(base_priority);
+                let record = jsonl::Record { priority, timestamp: &timestamp, msgid, sd, msg, stream, seq: sequence };
+                println!("{}", record.to_json(&formatter));
+            }
+            // Only --emit-jsonl has any use for these
+            #[cfg(not(feature = "gelf"))]
+            let _ = (stream, primary_override);
+            if gave_up {
+                echo(str);
+                return;
+            }
+            // The primary comes first, so a line it couldn't be given hasn't gone anywhere else either
+            let mut index = 0;
+            while let Some(sender) = senders.get_mut(index) {
+                let priority = sender.destination.priority_override.apply(base_priority);
+                let formatted = match sender.transport {
+                    Transport::Tls => formatter.format(priority, &timestamp, msgid, sd, msg),
+                    #[cfg(feature = "gelf")]
+                    Transport::Gelf | Transport::GelfUdp =>
+                        gelf::format(&formatter, priority, &now, msgid, sd, msg),
+                };
+                if sender.send(&formatted).is_ok() {
+                    // Generated messages have no stream, and aren't one of the command's lines
+                    if index == 0 && stream.is_some() {
+                        stats.lines_forwarded.fetch_add(1, Ordering::Relaxed);
+                    }
+                    index += 1;
+                } else if index > 0 {
+                    eprintln!("Carrying on without `{}`, which is only an --also-server.", sender.address());
+                    senders.remove(index);
+                } else {
+                    if !args.echo_on_failure {
+                        exit(127);
+                    }
+                    gave_up = true;
+                    echo(str);
+                    return;
+                }
+            }
+        };
 
-        let hostname = args.hostname.expect("The command line parser failed.");
-        let appname = args.appname.expect("The command line parser failed.");
+        // Messages we generate ourselves are carried in the same encoding as the captured lines
+        let encode_generated = |message: String| args.encode_msg.encode(message.into_bytes())
+            .expect("Generated messages are valid UTF-8.");
+        let mut rate_limiter = args.rate_limit.map(|rate| RateLimiter::new(
+            rate,
+            args.rate_limit_burst.map_or(rate.max(1.0), f64::from),
+            args.rate_limit_key,
+            args.rate_limit_max_keys as usize,
+        ));
+        let mut shedder = args.adaptive_shed.then(|| shed::Shedder::new(
+            args.shed_high_water,
+            args.shed_low_water,
+            args.shed_keep_one_in,
+        ));
+        let mut pacer = args.pace_fps.map(|fps| pace::Pacer::new(fps, draining.clone()));
+        let mut deduplicator = args.dedup.then(dedup::Deduplicator::default);
+        let mut dedup_timer = args.dedup_flush_interval.map(|secs| Interval::new(Duration::from_secs(secs)));
+        let mut drop_warner = args.drop_warn_interval.map(stats::DropWarner::new);
+        let mut drop_warn_timer = args.drop_warn_interval.map(|secs| Interval::new(Duration::from_secs(secs)));
         loop {
-            let result = receiver.recv().unwrap();
+            let timeout = [&dedup_timer, &drop_warn_timer].into_iter().flatten().map(Interval::time_left).min();
+            let result = match timeout {
+                Some(timeout) => match receiver.recv_timeout(timeout) {
+                    Ok(result) => Some(result),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => panic!("All senders hung up without an EOF."),
+                },
+                None => Some(receiver.recv().unwrap()),
+            };
+
+            if dedup_timer.as_mut().is_some_and(Interval::elapsed) {
+                if let Some(summary) = deduplicator.as_mut().and_then(|d| d.flush()) {
+                    deliver(None, &[], None, &encode_generated(summary));
+                }
+            }
+            if drop_warn_timer.as_mut().is_some_and(Interval::elapsed) {
+                if let Some(summary) = drop_warner.as_mut().and_then(|w| w.summary(&stats)) {
+                    eprintln!("{summary}");
+                }
+            }
+
             match result {
-                DeliverValue::Eof() => break,
-                DeliverValue::Line(str) => {
-                    // TODO: Enforce newline?
-                    // TODO: What if appname contains space?
-                    // TODO: Produce timestamp on sending thread in case this one is behind during a retry?
-                    // Timestamp format per https://www.rfc-editor.org/rfc/rfc5424#section-6
-                    // E.g: 2003-08-24T05:14:15.000003-07:00
-                    let timestamp = Utc::now().format("%Y-%m-%dT%H:%M:%S%.6f%:z");
-                    let formatted = format!("<{SYSLOG_PRIORITY}>{SYSLOG_VERSION} {timestamp} {hostname} {appname} - - - {str}");
-                    stream.write(formatted.as_bytes()).unwrap();
+                None => continue,
+                Some(DeliverValue::Eof(exit_code)) => {
+                    if let Some(summary) = deduplicator.as_mut().and_then(|d| d.flush()) {
+                        deliver(None, &[], None, &encode_generated(summary));
+                    }
+                    if args.run_summary {
+                        let (element, message) = summary::summary(&stats, started.elapsed(), exit_code);
+                        deliver(Some(summary::SUMMARY_MSGID), &[element], None, &encode_generated(message));
+                    }
+                    break;
+                },
+                Some(DeliverValue::Connection(event)) => {
+                    let (msgid, element, message) = connection_events::message(&event);
+                    deliver(Some(msgid), &[element], None, &encode_generated(message));
+                },
+                #[cfg(unix)]
+                Some(DeliverValue::Signal(msgid, message)) =>
+                    deliver(Some(msgid), &[], None, &encode_generated(message)),
+                Some(DeliverValue::Line(str, stream)) => {
+                    stats.queue_depth.fetch_sub(1, Ordering::Relaxed);
+                    if shedder.as_mut().is_some_and(|shedder| !shedder.allow(&stats)) {
+                        continue;
+                    }
+                    if rate_limiter.as_mut().is_some_and(|limiter| !limiter.allow(&str, stream, &stats)) {
+                        continue;
+                    }
+                    if let Some(pacer) = pacer.as_mut() {
+                        pacer.wait();
+                    }
+                    match deduplicator.as_mut() {
+                        Some(deduplicator) => {
+                            // Any repeat summary comes first, and is about the previous line
+                            let mut lines = deduplicator.push(str, &stats);
+                            if let Some(line) = lines.pop() {
+                                for summary in lines {
+                                    deliver(None, &[], None, &encode_generated(summary));
+                                }
+                                deliver(None, &[], Some(stream), &line);
+                            }
+                        },
+                        None => deliver(None, &[], Some(stream), &str),
+                    }
                 },
             };
         }
+        for sender in senders.iter_mut() {
+            sender.close();
+        }
+        if let Some(local_file) = local_file {
+            local_file.close();
+        }
+        gave_up
     });
 
     // Wait for the threads to finish consuming the child process's output
-    stderr_handler.join().unwrap();
-    stdout_handler.join().unwrap();
-    sender.send(DeliverValue::Eof()).expect("Unable to send EOF to consuming threads.");
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    // Wait for the child to exit, so that a signal that killed it can be logged with its output
+    let status = child_process.wait();
+    main_draining.store(true, Ordering::Relaxed);
+    if let Some(secs) = args.post_exit_timeout {
+        // Not joined: this ends the process if delivery doesn't finish first
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(secs));
+            let waiting = delivery_stats.queue_depth.load(Ordering::Relaxed);
+            eprintln!("Delivery still hadn't finished {secs}s after the command exited; giving up. {waiting} \
+                       lines still waiting to be sent, and any being sent, were not delivered.");
+            exit(127);
+        });
+    }
+    #[cfg(unix)]
+    if let Some(signal) = status.as_ref().ok().and_then(std::os::unix::process::ExitStatusExt::signal) {
+        let message = format!("The command was killed by {}\n", signals::name(signal));
+        sender.send(DeliverValue::Signal(signals::KILLED_MSGID, message)).expect("Unable to send to consuming threads.");
+    }
+    let exit_code = status.as_ref().ok().and_then(exit_code);
+    sender.send(DeliverValue::Eof(exit_code)).expect("Unable to send EOF to consuming threads.");
     // Wait for delivery of remaining messages to flush
-    delivery.join().unwrap();
-    // Wait for the child to exit
-    match child_process.wait() {
-        Ok(status) => match status.code() {
+    let gave_up = delivery.join().unwrap();
+    match status {
+        Ok(_) if gave_up => {
+            eprintln!("Delivery failed, so the command's output was written to stderr instead.");
+            exit(127);
+        },
+        Ok(_) => match exit_code {
             // Preserve the exit code of the child
-            Some(status) => exit(status),
+            Some(code) => exit(code),
             None => {
                 eprintln!("The subcommand did not return an exit code.");
                 exit(40);
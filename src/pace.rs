@@ -0,0 +1,32 @@
+// Spreading bursts of lines out evenly over time, per `--pace-fps`.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct Pacer {
+    interval: Duration,
+    /// The earliest the next line may go out.
+    next: Instant,
+    /// Set once the command has exited, after which whatever is still waiting goes out at once.
+    draining: Arc<AtomicBool>,
+}
+
+impl Pacer {
+    pub fn new(fps: f64, draining: Arc<AtomicBool>) -> Pacer {
+        Pacer { interval: Duration::from_secs_f64(1.0 / fps), next: Instant::now(), draining }
+    }
+
+    /// Waits until the next line may be delivered. Lines that arrive slower than the pace go
+    /// out straight away; a burst is released one line per interval. Unused time doesn't
+    /// accumulate, so there's never a burst on the way out.
+    pub fn wait(&mut self) {
+        if self.draining.load(Ordering::Relaxed) {
+            return;
+        }
+        let now = Instant::now();
+        thread::sleep(self.next.saturating_duration_since(now));
+        self.next = self.next.max(now) + self.interval;
+    }
+}
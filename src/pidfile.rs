@@ -0,0 +1,22 @@
+// Recording the child's PID for process monitors, per `--pidfile`.
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The pidfile we wrote, so that whichever thread ends the process can remove it.
+static PIDFILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub fn write(path: &Path, pid: u32) {
+    match std::fs::write(path, format!("{pid}\n")) {
+        Ok(()) => *PIDFILE.lock().unwrap() = Some(path.to_path_buf()),
+        Err(e) => eprintln!("Unable to write pidfile `{path:?}`: {e}"),
+    }
+}
+
+/// Removes the pidfile, if one was written.
+pub fn remove() {
+    if let Some(path) = PIDFILE.lock().unwrap_or_else(|e| e.into_inner()).take() {
+        if let Err(e) = std::fs::remove_file(&path) {
+            eprintln!("Unable to remove pidfile `{path:?}`: {e}");
+        }
+    }
+}
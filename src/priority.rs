@@ -0,0 +1,102 @@
+// Facilities, severities and the PRI value computed from them. See RFC 5424 sec. 6.2.1.
+
+/// Facility names, indexed by their numeric code.
+const FACILITY_NAMES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news",
+    "uucp", "cron", "authpriv", "ftp", "ntp", "audit", "alert", "clock",
+    "local0", "local1", "local2", "local3", "local4", "local5", "local6", "local7",
+];
+
+/// Severity names, indexed by their numeric code.
+const SEVERITY_NAMES: [&str; 8] = [
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+/// Other spellings of severities that `logger(1)` and friends commonly accept.
+const SEVERITY_ALIASES: [(&str, u8); 7] = [
+    ("panic", 0), ("emergency", 0), ("critical", 2), ("error", 3), ("warn", 4),
+    ("informational", 6), ("information", 6),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Priority {
+    pub facility: u8,
+    pub severity: u8,
+}
+
+/// The priority used when nothing else is configured: mail.info.
+pub const DEFAULT_PRIORITY: Priority = Priority { facility: 2, severity: 6 };
+
+impl Priority {
+    /// The numeric PRI value that goes between the angle brackets.
+    pub fn code(&self) -> u8 {
+        self.facility * 8 + self.severity
+    }
+
+    /// The priority spelled out as `FACILITY.SEVERITY` (e.g. `mail.info` for `<22>`), as
+    /// `parse_priority` accepts it, for the wrapper's own output. Never part of a message.
+    pub fn name(&self) -> String {
+        format!("{}.{}", facility_name(self.facility), severity_name(self.severity))
+    }
+}
+
+/// Replaces the facility and/or severity of whatever priority a message would otherwise
+/// have been sent with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PriorityOverride {
+    pub facility: Option<u8>,
+    pub severity: Option<u8>,
+}
+
+impl PriorityOverride {
+    pub fn apply(&self, priority: Priority) -> Priority {
+        Priority {
+            facility: self.facility.unwrap_or(priority.facility),
+            severity: self.severity.unwrap_or(priority.severity),
+        }
+    }
+}
+
+pub fn facility_name(facility: u8) -> &'static str {
+    FACILITY_NAMES[usize::from(facility)]
+}
+
+pub fn severity_name(severity: u8) -> &'static str {
+    SEVERITY_NAMES[usize::from(severity)]
+}
+
+/// Parses `FACILITY[.SEVERITY]`, as `logger -p` accepts it (e.g. `local3.warning`). The
+/// severity defaults to that of `DEFAULT_PRIORITY`.
+pub fn parse_priority(value: &str) -> Result<Priority, String> {
+    let (facility, severity) = match value.split_once('.') {
+        Some((facility, severity)) => (facility, Some(severity)),
+        None => (value, None),
+    };
+    Ok(Priority {
+        facility: parse_facility(facility)?,
+        severity: severity.map_or(Ok(DEFAULT_PRIORITY.severity), parse_severity)?,
+    })
+}
+
+/// Parses a facility given either by name (`local3`) or by number (`19`).
+pub fn parse_facility(value: &str) -> Result<u8, String> {
+    let name = value.to_ascii_lowercase();
+    let code = match name.parse::<u8>() {
+        Ok(code) => Some(code).filter(|code| usize::from(*code) < FACILITY_NAMES.len()),
+        Err(_) => FACILITY_NAMES.iter().position(|f| *f == name).map(|code| code as u8),
+    };
+    code.ok_or_else(|| format!("`{value}` is not a syslog facility (expected one of {}, or 0-23)",
+                               FACILITY_NAMES.join(", ")))
+}
+
+/// Parses a severity given either by name (`warning`, `warn`) or by number (`4`).
+pub fn parse_severity(value: &str) -> Result<u8, String> {
+    let name = value.to_ascii_lowercase();
+    let code = match name.parse::<u8>() {
+        Ok(code) => Some(code).filter(|code| usize::from(*code) < SEVERITY_NAMES.len()),
+        Err(_) => SEVERITY_NAMES.iter().position(|s| *s == name).map(|code| code as u8)
+            .or_else(|| SEVERITY_ALIASES.iter().find(|(alias, _)| *alias == name).map(|(_, code)| *code)),
+    };
+    code.ok_or_else(|| format!("`{value}` is not a syslog severity (expected one of {}, or 0-7)",
+                               SEVERITY_NAMES.join(", ")))
+}
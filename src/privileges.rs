@@ -0,0 +1,58 @@
+// Resolving the user and group to run the child as, for `--user` and `--group`.
+
+/// A user to run the child as, along with their primary group.
+#[derive(Clone, Copy, Debug)]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Resolves a user name (or numeric uid) to its uid and primary gid.
+#[cfg(unix)]
+pub fn parse_user(value: &str) -> Result<User, String> {
+    let name = std::ffi::CString::new(value).map_err(|_| format!("`{value}` is not a valid user name"))?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let status = unsafe {
+        libc::getpwnam_r(name.as_ptr(), &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+    if status == 0 && !result.is_null() {
+        return Ok(User { uid: passwd.pw_uid, gid: passwd.pw_gid });
+    }
+    if let Ok(uid) = value.parse::<u32>() {
+        let status = unsafe {
+            libc::getpwuid_r(uid, &mut passwd, buffer.as_mut_ptr(), buffer.len(), &mut result)
+        };
+        // A uid without a passwd entry is still usable; leave its group as that uid too
+        let gid = if status == 0 && !result.is_null() { passwd.pw_gid } else { uid };
+        return Ok(User { uid, gid });
+    }
+    Err(format!("Unknown user `{value}`"))
+}
+
+/// Resolves a group name (or numeric gid) to its gid.
+#[cfg(unix)]
+pub fn parse_group(value: &str) -> Result<u32, String> {
+    let name = std::ffi::CString::new(value).map_err(|_| format!("`{value}` is not a valid group name"))?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let status = unsafe {
+        libc::getgrnam_r(name.as_ptr(), &mut group, buffer.as_mut_ptr(), buffer.len(), &mut result)
+    };
+    if status == 0 && !result.is_null() {
+        return Ok(group.gr_gid);
+    }
+    value.parse::<u32>().map_err(|_| format!("Unknown group `{value}`"))
+}
+
+#[cfg(not(unix))]
+pub fn parse_user(_value: &str) -> Result<User, String> {
+    Err("Running the command as another user is only supported on Unix".into())
+}
+
+#[cfg(not(unix))]
+pub fn parse_group(_value: &str) -> Result<u32, String> {
+    Err("Running the command as another group is only supported on Unix".into())
+}
@@ -0,0 +1,88 @@
+// Token-bucket rate limiting of captured lines, either globally or per key, per `--rate-limit`.
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::stats::{DropReason, Stats};
+
+/// Which part of a line decides the bucket it's charged to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// Which of the command's output streams the line came from.
+    Stream,
+    /// The Nth (1-based) whitespace-separated word of the line, such as a level column.
+    Word(usize),
+}
+
+impl RateLimitKey {
+    /// Parses `stream` or `word:N`.
+    pub fn parse(value: &str) -> Result<RateLimitKey, String> {
+        match value.split_once(':') {
+            None if value == "stream" => Ok(RateLimitKey::Stream),
+            Some(("word", n)) => match n.parse() {
+                Ok(n) if n > 0 => Ok(RateLimitKey::Word(n)),
+                _ => Err(format!("`{n}` is not a word number (expected 1 or more)")),
+            },
+            _ => Err(format!("`{value}` is not a rate limit key (expected stream or word:N)")),
+        }
+    }
+
+    fn of<'a>(&self, line: &'a str, stream: &'a str) -> &'a str {
+        match self {
+            RateLimitKey::Stream => stream,
+            RateLimitKey::Word(n) => line.split_whitespace().nth(n - 1).unwrap_or(""),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Lines per second each bucket refills at.
+    rate: f64,
+    /// How many lines a bucket holds, i.e. the largest burst let through at once.
+    burst: f64,
+    key: Option<RateLimitKey>,
+    /// Once this many buckets exist, the longest-idle one is forgotten to make room.
+    max_keys: usize,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: f64, burst: f64, key: Option<RateLimitKey>, max_keys: usize) -> RateLimiter {
+        RateLimiter { rate, burst, key, max_keys, buckets: HashMap::new() }
+    }
+
+    /// Returns whether `line` may be forwarded, recording a drop if not.
+    pub fn allow(&mut self, line: &str, stream: &str, stats: &Stats) -> bool {
+        let key = match &self.key {
+            Some(key) => key.of(line, stream),
+            None => "",
+        };
+        let now = Instant::now();
+        if !self.buckets.contains_key(key) && self.buckets.len() >= self.max_keys {
+            let idlest = self.buckets.iter().min_by_key(|(_, bucket)| bucket.last_refill).map(|(key, _)| key.clone());
+            if let Some(idlest) = idlest {
+                self.buckets.remove(&idlest);
+            }
+        }
+        let bucket = self.buckets.entry(key.to_string())
+            .or_insert(Bucket { tokens: self.burst, last_refill: now });
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * self.rate;
+        bucket.tokens = (bucket.tokens + refill).min(self.burst);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+        stats.record_drop(DropReason::RateLimit);
+        if self.key.is_some() {
+            stats.record_keyed_drop(if key.is_empty() { "(none)" } else { key }, self.max_keys);
+        }
+        false
+    }
+}
@@ -0,0 +1,117 @@
+// Scrubbing secrets and personal data out of lines before they leave the host, per `--redact`.
+use std::sync::atomic::Ordering;
+
+use regex::bytes::{Captures, Regex};
+
+use crate::stats::Stats;
+
+/// Decides whether a match is really what the rule is after, for patterns that are too loose
+/// on their own.
+type Check = fn(&[u8]) -> bool;
+
+/// A bundle of common redaction rules, as `(REGEX, REPLACEMENT, CHECK)` triples.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Preset {
+    /// Email addresses, payment card numbers and US social security numbers.
+    Pii,
+    /// Passwords, tokens and API keys given as `key=value`, bearer tokens, AWS access key IDs
+    /// and PEM private key headers.
+    Secrets,
+}
+
+impl Preset {
+    fn rules(&self) -> &'static [(&'static str, &'static str, Option<Check>)] {
+        match self {
+            Preset::Pii => &[
+                (r"[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Za-z]{2,}", "[EMAIL]", None),
+                // Plenty of other numbers are this long, such as millisecond timestamps
+                (r"\b(?:\d[ -]?){12,18}\d\b", "[CARD]", Some(passes_luhn)),
+                (r"\b\d{3}-\d{2}-\d{4}\b", "[SSN]", None),
+            ],
+            Preset::Secrets => &[
+                (r"(?i)\b(password|passwd|pwd|secret|token|api[_-]?key|access[_-]?key)(\s*[=:]\s*)\S+", "${1}${2}[REDACTED]", None),
+                (r"(?i)\b(bearer\s+)[A-Za-z0-9._~+/-]+=*", "${1}[REDACTED]", None),
+                (r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b", "[AWS_KEY]", None),
+                (r"-----BEGIN [A-Z ]*PRIVATE KEY-----", "[PRIVATE_KEY]", None),
+            ],
+        }
+    }
+}
+
+/// Whether the digits in `text` have a valid Luhn check digit, as payment card numbers do.
+fn passes_luhn(text: &[u8]) -> bool {
+    let digits = text.iter().rev().filter(|byte| byte.is_ascii_digit()).map(|byte| u32::from(byte - b'0'));
+    let sum: u32 = digits.enumerate()
+        .map(|(i, digit)| match (i % 2, digit * 2) {
+            (0, _) => digit,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// One `--redact` rule.
+#[derive(Clone, Debug)]
+pub struct Rule {
+    regex: Regex,
+    replacement: String,
+    check: Option<Check>,
+}
+
+impl Rule {
+    /// Parses `REGEX=REPLACEMENT`. The last `=` separates the two, so the regex may contain `=`
+    /// but the replacement can't. The replacement may refer to capture groups, as in `${1}`.
+    pub fn parse(value: &str) -> Result<Rule, String> {
+        let (regex, replacement) = value.rsplit_once('=')
+            .ok_or_else(|| format!("`{value}` is not of the form REGEX=REPLACEMENT"))?;
+        Rule::new(regex, replacement, None)
+    }
+
+    fn new(regex: &str, replacement: &str, check: Option<Check>) -> Result<Rule, String> {
+        let regex = Regex::new(regex).map_err(|e| format!("Invalid redaction regex `{regex}`: {e}"))?;
+        Ok(Rule { regex, replacement: replacement.into(), check })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Redactor {
+    rules: Vec<Rule>,
+}
+
+impl Redactor {
+    /// Applies the presets' rules first, then the custom ones, in the order given.
+    pub fn new(presets: &[Preset], rules: Vec<Rule>) -> Redactor {
+        let mut all_rules: Vec<Rule> = presets.iter()
+            .flat_map(Preset::rules)
+            .map(|(regex, replacement, check)| Rule::new(regex, replacement, *check).expect("Preset regexes are valid."))
+            .collect();
+        all_rules.extend(rules);
+        Redactor { rules: all_rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Replaces every match of every rule in `line`, counting the replacements in `stats`.
+    pub fn redact(&self, mut line: Vec<u8>, stats: &Stats) -> Vec<u8> {
+        for Rule { regex, replacement, check } in &self.rules {
+            let mut redactions = 0;
+            let redacted = regex.replace_all(&line, |captures: &Captures| {
+                if check.is_some_and(|check| !check(&captures[0])) {
+                    return captures[0].to_vec();
+                }
+                redactions += 1;
+                let mut expanded = vec![];
+                captures.expand(replacement.as_bytes(), &mut expanded);
+                expanded
+            });
+            if redactions > 0 {
+                line = redacted.into_owned();
+                stats.redactions.fetch_add(redactions, Ordering::Relaxed);
+            }
+        }
+        line
+    }
+}
@@ -0,0 +1,595 @@
+// Delivering formatted frames to a syslog server.
+use std::io::{self, Read, Write};
+#[cfg(feature = "gelf")]
+use std::net::UdpSocket;
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use retry::delay::Exponential;
+use retry::OperationResult;
+use rustls::{ClientConfig, ClientConnection, ServerName, StreamOwned};
+
+#[cfg(feature = "gelf")]
+use crate::gelf;
+use crate::priority::{self, PriorityOverride};
+use crate::source_port::{self, SourcePortRange};
+use crate::stats::Stats;
+
+const DEFAULT_SYSLOG_PORT: u16 = 6514;
+const INITIAL_RETRY_DELAY_MS: u64 = 100;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+/// How long to wait for the server to acknowledge the end of the connection.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// While waiting for the server at startup, we want to notice it coming up promptly.
+const MAX_WAIT_DELAY: Duration = Duration::from_secs(2);
+
+/// How frames are formatted and carried to the server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Transport {
+    /// RFC 5424 syslog messages over TLS, per RFC 5425.
+    Tls,
+    /// GELF documents over TLS, each terminated by a null byte.
+    #[cfg(feature = "gelf")]
+    Gelf,
+    /// GELF documents over UDP, chunked when they don't fit in one datagram.
+    #[cfg(feature = "gelf")]
+    GelfUdp,
+}
+
+impl Transport {
+    fn default_port(&self) -> u16 {
+        match self {
+            Transport::Tls => DEFAULT_SYSLOG_PORT,
+            #[cfg(feature = "gelf")]
+            Transport::Gelf | Transport::GelfUdp => gelf::DEFAULT_GELF_PORT,
+        }
+    }
+}
+
+/// How consecutive syslog messages are told apart on the TLS stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Framing {
+    /// `LEN SP MSG`, per RFC 5425 sec. 4.3.
+    OctetCounting,
+    /// Each message followed by a delimiter, per RFC 6587 sec. 3.4.2.
+    Trailer(u8),
+}
+
+impl Framing {
+    /// Parses `octet-counting`, `lf`, `nul` or `trailer:BYTE`, where BYTE is a number (decimal,
+    /// or hex with a `0x` prefix) or a single ASCII character.
+    pub fn parse(value: &str) -> Result<Framing, String> {
+        match value.split_once(':') {
+            None if value == "octet-counting" => Ok(Framing::OctetCounting),
+            None if value == "lf" => Ok(Framing::Trailer(b'\n')),
+            None if value == "nul" => Ok(Framing::Trailer(b'\0')),
+            Some(("trailer", byte)) => {
+                let parsed = match byte.strip_prefix("0x") {
+                    Some(hex) => u8::from_str_radix(hex, 16).ok(),
+                    None => byte.parse().ok(),
+                };
+                parsed
+                    .or_else(|| Some(byte.as_bytes()).filter(|b| b.len() == 1 && b[0].is_ascii()).map(|b| b[0]))
+                    .map(Framing::Trailer)
+                    .ok_or_else(|| format!("`{byte}` is not a byte (expected e.g. 10, 0x0a or a single character)"))
+            },
+            _ => Err(format!("`{value}` is not a framing (expected octet-counting, lf, nul or trailer:BYTE)")),
+        }
+    }
+}
+
+/// A syslog server to deliver to, as given on the command line.
+#[derive(Clone, Debug)]
+pub struct Destination {
+    pub host: String,
+    /// Defaults to the standard port for the transport in use.
+    pub port: Option<u16>,
+    /// Applied to the priority of every message sent to this destination.
+    pub priority_override: PriorityOverride,
+    /// An already-connected socket inherited from our parent, to use instead of connecting
+    /// to `host` (which is still what the certificate is verified against). Unix only.
+    pub inherited_fd: Option<i32>,
+}
+
+impl Destination {
+    /// Parses `HOST[:PORT]`, optionally followed by `,facility=NAME` and/or `,severity=NAME`.
+    /// IPv6 addresses must be bracketed, as in `[::1]:6514`.
+    pub fn parse(value: &str) -> Result<Destination, String> {
+        let mut parts = value.split(',');
+        let server = parts.next().unwrap_or_default();
+        let (host, port_str) = match server.strip_prefix('[').and_then(|s| s.split_once(']')) {
+            Some((host, rest)) => match rest.strip_prefix(':') {
+                Some(port_str) => (host, Some(port_str)),
+                None if rest.is_empty() => (host, None),
+                None => return Err(format!("Unexpected `{rest}` after IPv6 address `{host}`")),
+            },
+            None => match server.split_once(':') {
+                Some((host, port_str)) => (host, Some(port_str)),
+                None => (server, None),
+            },
+        };
+        let port = match port_str {
+            Some(port_str) => Some(port_str.parse().map_err(|e| format!("Invalid port `{port_str}`: {e}"))?),
+            None => None,
+        };
+        if host.is_empty() {
+            return Err(format!("`{value}` does not name a server"));
+        }
+
+        let mut priority_override = PriorityOverride::default();
+        for option in parts {
+            match option.split_once('=') {
+                Some(("facility", facility)) => priority_override.facility = Some(priority::parse_facility(facility)?),
+                Some(("severity", severity)) => priority_override.severity = Some(priority::parse_severity(severity)?),
+                _ => return Err(format!("Unknown destination option `{option}` (expected facility=NAME or severity=NAME)")),
+            }
+        }
+
+        Ok(Destination { host: host.into(), port, priority_override, inherited_fd: None })
+    }
+
+    /// The name the server's certificate is verified against.
+    pub fn server_name(&self) -> Result<ServerName, String> {
+        let host = self.host.as_str();
+        // A collector addressed by IP presents a certificate with an IP SAN, not a DNS SAN
+        match host.parse::<IpAddr>() {
+            Ok(ip) => Ok(ServerName::IpAddress(ip)),
+            Err(_) => ServerName::try_from(host).map_err(|e| format!("`{host}` is not a valid server name: {e}")),
+        }
+    }
+}
+
+/// Delivery to a destination has failed for good. The reason has already been reported.
+#[derive(Debug)]
+pub struct GaveUp;
+
+enum Connection {
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+    #[cfg(feature = "gelf")]
+    Udp(UdpSocket),
+}
+
+/// Whether `error` is the server's certificate being rejected during the TLS handshake, which
+/// no amount of retrying will change, unlike the network failing.
+fn is_certificate_error(error: &io::Error) -> bool {
+    let tls_error = error.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>());
+    matches!(tls_error, Some(
+        rustls::Error::InvalidCertificate(_)
+        | rustls::Error::NoCertificatesPresented
+        | rustls::Error::UnsupportedNameType
+        | rustls::Error::InvalidCertRevocationList(_)
+    ))
+}
+
+/// How a `Sender` behaves, shared by every destination.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Only applies to syslog over TLS; GELF has framing of its own.
+    pub framing: Framing,
+    pub flush_on_each_line: bool,
+    /// How many times to try reconnecting after losing the connection before giving up, once
+    /// something has been delivered.
+    pub max_retries: u8,
+    /// How many times to retry connecting before anything has been delivered. Failing this
+    /// early usually means the configuration is wrong, so this budget is kept small.
+    pub startup_retries: u8,
+    /// The least time allowed between the starts of two consecutive connection attempts,
+    /// whatever the backoff schedule says.
+    pub min_reconnect_interval: Duration,
+    /// How long to keep trying to make the initial connection before giving up.
+    pub wait_for_server: Duration,
+    /// How long to reuse the addresses the server's name resolved to before resolving it again.
+    pub dns_cache_ttl: Duration,
+    /// How long the TLS handshake may take before the connection is written off.
+    pub handshake_timeout: Option<Duration>,
+    /// How long a connection must stay up for the next outage's backoff to start over from the
+    /// first (immediate) attempt. Until then, reconnection attempts pick up the backoff schedule
+    /// where the last outage left it, so that a flapping connection backs off progressively.
+    /// With `None`, every outage starts over.
+    pub backoff_reset_after: Option<Duration>,
+    /// The local ports to connect from. Any will do if `None`.
+    pub source_ports: Option<SourcePortRange>,
+}
+
+/// See `Sender::on_delivered`.
+type DeliveryCallback = Box<dyn FnMut(&str) + Send>;
+/// See `Sender::on_connection_event`.
+type ConnectionEventCallback = Box<dyn FnMut(ConnectionEvent) + Send>;
+
+/// A change in the state of a `Sender`'s connection.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// The connection to `peer` (as `HOST:PORT`) was found to be broken, by `error`.
+    Down { peer: String, error: String },
+    /// The connection to `peer` was re-established, `downtime` after it was found to be broken,
+    /// on the `attempts`th attempt.
+    Up { peer: String, downtime: Duration, attempts: usize },
+}
+
+pub struct Sender {
+    pub destination: Destination,
+    pub transport: Transport,
+    port: u16,
+    server_name: ServerName,
+    config: Arc<ClientConfig>,
+    options: Options,
+    connection: Option<Connection>,
+    last_connect_attempt: Option<Instant>,
+    /// rustls only reports a failure to write out a frame on the *next* write, so we hold on to
+    /// the previous frame in order to re-send it after reconnecting. This means the frame before
+    /// a broken connection may be delivered twice.
+    previous_frame: Option<String>,
+    /// The addresses the server's name last resolved to, and when.
+    resolved: Option<(Vec<SocketAddr>, Instant)>,
+    /// Whether a write has ever succeeded, after which --max-retries applies rather than the
+    /// startup budget.
+    established: bool,
+    /// When the current connection was made.
+    connected_at: Option<Instant>,
+    /// Reconnection attempts made since the backoff schedule last started over.
+    backoff_step: usize,
+    stats: Arc<Stats>,
+    on_delivered: Option<DeliveryCallback>,
+    on_connection_event: Option<ConnectionEventCallback>,
+}
+
+impl Sender {
+    /// Connects to the destination, giving up if that isn't possible within `startup_retries`
+    /// retries or the `wait_for_server` grace period, whichever lasts longer.
+    pub fn connect(destination: Destination, transport: Transport, config: Arc<ClientConfig>, options: Options,
+                   stats: Arc<Stats>) -> Result<Sender, GaveUp> {
+        let port = destination.port.unwrap_or_else(|| transport.default_port());
+        let server_name = destination.server_name().map_err(|e| {
+            eprintln!("{e}");
+            GaveUp
+        })?;
+
+        let mut sender = Sender {
+            destination, transport, port, server_name, config, options,
+            connection: None,
+            last_connect_attempt: None,
+            previous_frame: None,
+            resolved: None,
+            established: false,
+            connected_at: None,
+            backoff_step: 0,
+            stats,
+            on_delivered: None,
+            on_connection_event: None,
+        };
+        let address = sender.address();
+        let deadline = Instant::now() + sender.options.wait_for_server;
+        let startup_retries = usize::from(sender.options.startup_retries);
+        let delays = Exponential::from_millis(INITIAL_RETRY_DELAY_MS)
+            .map(|delay| delay.min(MAX_WAIT_DELAY))
+            .enumerate()
+            .map_while(|(retry, delay)| {
+                if retry < startup_retries {
+                    return Some(delay);
+                }
+                let time_left = deadline.saturating_duration_since(Instant::now());
+                (!time_left.is_zero()).then(|| delay.min(time_left))
+            });
+        let result = retry::retry_with_index(delays, |attempt| match sender.open() {
+            Ok(connection) => OperationResult::Ok(connection),
+            Err(e) if is_certificate_error(&e) => OperationResult::Err(e),
+            // The failed connection closed the inherited fd, whose number may since have been reused
+            Err(e) if sender.destination.inherited_fd.is_some() => OperationResult::Err(e),
+            Err(e) => {
+                if attempt <= startup_retries as u64 || Instant::now() < deadline {
+                    eprintln!("Waiting for `{address}` to accept connections: {e}");
+                }
+                OperationResult::Retry(e)
+            },
+        });
+        match result {
+            Ok(connection) => {
+                sender.connection = Some(connection);
+                sender.connected_at = Some(Instant::now());
+            },
+            Err(e) if is_certificate_error(&e.error) => {
+                eprintln!("`{address}` presented a certificate that can't be trusted, so not retrying: {}", e.error);
+                return Err(GaveUp);
+            },
+            Err(e) => {
+                eprintln!("Unable to connect to `{address}`: {}", e.error);
+                return Err(GaveUp);
+            },
+        }
+        Ok(sender)
+    }
+
+    /// Calls `callback` with each frame once it has been written to the connection.
+    ///
+    /// Delivery is at least once: after a reconnect, the frame before the one that found the
+    /// connection broken is sent again (see `previous_frame`), and the callback is called for it
+    /// again. Having been written doesn't mean the server has received the frame, as syslog has
+    /// no acknowledgements; a frame still in flight when the connection breaks is re-sent only
+    /// if it's the previous one.
+    pub fn on_delivered(&mut self, callback: impl FnMut(&str) + Send + 'static) {
+        self.on_delivered = Some(Box::new(callback));
+    }
+
+    /// Calls `callback` whenever the connection is lost, and again if it's re-established.
+    /// Problems making the initial connection aren't reported, and neither is a connection
+    /// that's closed on purpose.
+    pub fn on_connection_event(&mut self, callback: impl FnMut(ConnectionEvent) + Send + 'static) {
+        self.on_connection_event = Some(Box::new(callback));
+    }
+
+    fn connection_event(&mut self, event: ConnectionEvent) {
+        if let Some(on_connection_event) = &mut self.on_connection_event {
+            on_connection_event(event);
+        }
+    }
+
+    fn delivered(&mut self, frame: &str) {
+        if let Some(on_delivered) = &mut self.on_delivered {
+            on_delivered(frame);
+        }
+    }
+
+    /// Sends a frame, reconnecting (with exponential backoff) if the connection has been lost.
+    /// Gives up if the connection can't be re-established.
+    pub fn send(&mut self, frame: &str) -> Result<(), GaveUp> {
+        let error = match self.write(frame) {
+            Ok(()) => {
+                self.previous_frame = Some(frame.to_string());
+                self.established = true;
+                self.delivered(frame);
+                return Ok(());
+            },
+            Err(error) => error,
+        };
+        let address = self.address();
+        self.connection = None;
+        eprintln!("Lost connection to `{address}`: {error}");
+        let lost_at = Instant::now();
+        self.connection_event(ConnectionEvent::Down { peer: address.clone(), error: error.to_string() });
+        if self.destination.inherited_fd.is_some() {
+            eprintln!("An inherited socket can't be reconnected.");
+            return Err(GaveUp);
+        }
+        // Until something has got through, a failure is more likely a misconfiguration than a blip
+        let max_retries = if self.established { self.options.max_retries } else { self.options.startup_retries };
+        if max_retries == 0 {
+            return Err(GaveUp);
+        }
+
+        // The first reconnection attempt is immediate, then each is followed by a growing delay.
+        // After a connection that didn't stay up for `backoff_reset_after`, the schedule carries
+        // on from where the last outage left it instead.
+        let stable = match (self.options.backoff_reset_after, self.connected_at) {
+            (Some(reset_after), Some(connected_at)) => connected_at.elapsed() >= reset_after,
+            _ => true,
+        };
+        let step = if stable { 0 } else { self.backoff_step };
+        let mut delays = Exponential::from_millis(INITIAL_RETRY_DELAY_MS)
+            .map(|delay| delay.min(MAX_RETRY_DELAY))
+            .skip(step.saturating_sub(1));
+        if step > 0 {
+            thread::sleep(delays.next().expect("The backoff schedule is endless."));
+        }
+        let delays = delays.take(usize::from(max_retries) - 1);
+        let mut attempts = 0;
+        let result = retry::retry_with_index(delays, |attempt| {
+            attempts = attempt as usize;
+            eprintln!("Reconnecting to `{address}` (attempt {attempt} of {max_retries})");
+            let previous_frame = self.previous_frame.take();
+            let result = self.open().and_then(|connection| {
+                self.connection = Some(connection);
+                if let Some(previous_frame) = &previous_frame {
+                    self.write(previous_frame)?;
+                }
+                self.write(frame)
+            });
+            self.previous_frame = previous_frame;
+            match result {
+                Ok(()) => OperationResult::Ok(()),
+                Err(e) => {
+                    eprintln!("Unable to reconnect to `{address}`: {e}");
+                    self.connection = None;
+                    if is_certificate_error(&e) {
+                        return OperationResult::Err(e);
+                    }
+                    OperationResult::Retry(e)
+                },
+            }
+        });
+        self.backoff_step = step + attempts;
+        if let Err(e) = result {
+            eprintln!("Giving up on `{address}` after {} attempts: {}", e.tries, e.error);
+            return Err(GaveUp);
+        }
+        self.connected_at = Some(Instant::now());
+        self.connection_event(ConnectionEvent::Up { peer: address, downtime: lost_at.elapsed(), attempts });
+        if let Some(previous_frame) = self.previous_frame.take() {
+            self.delivered(&previous_frame);
+        }
+        self.previous_frame = Some(frame.to_string());
+        self.established = true;
+        self.stats.reconnects.fetch_add(1, Ordering::Relaxed);
+        self.delivered(frame);
+        Ok(())
+    }
+
+    /// Ends the connection cleanly, once everything sent so far has reached the server.
+    ///
+    /// Simply dropping the connection isn't enough: if the server has sent us anything we
+    /// haven't read (such as TLS session tickets), closing the socket makes the kernel reset
+    /// the connection, and the server may then discard what it hasn't yet read from us. So we
+    /// send close_notify, stop writing, and read until the server closes its side too.
+    pub fn close(&mut self) {
+        let Some(Connection::Tls(mut stream)) = self.connection.take() else {
+            return;
+        };
+        stream.conn.send_close_notify();
+        let result = stream.flush()
+            .and_then(|()| stream.sock.shutdown(Shutdown::Write))
+            .and_then(|()| stream.sock.set_read_timeout(Some(CLOSE_TIMEOUT)))
+            .and_then(|()| {
+                let mut discard = [0; 4096];
+                while stream.read(&mut discard)? > 0 {}
+                Ok(())
+            });
+        match result {
+            Ok(()) => {},
+            // Some servers just hang up rather than sending close_notify back
+            Err(e) if matches!(e.kind(), io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset) => {},
+            Err(e) => eprintln!("Unable to cleanly close the connection to `{}`: {e}", self.address()),
+        }
+    }
+
+    /// The destination's `HOST:PORT`, for messages.
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.destination.host, self.port)
+    }
+
+    /// Opens a fresh connection to the destination. For TLS, this includes the handshake, so a
+    /// server whose certificate isn't trusted fails here rather than on the first write.
+    fn open(&mut self) -> io::Result<Connection> {
+        if let Some(last_attempt) = self.last_connect_attempt {
+            let next_allowed = last_attempt + self.options.min_reconnect_interval;
+            thread::sleep(next_allowed.saturating_duration_since(Instant::now()));
+        }
+        self.last_connect_attempt = Some(Instant::now());
+
+        if let Some(fd) = self.destination.inherited_fd {
+            return self.open_inherited(fd);
+        }
+
+        let addresses = self.resolve()?;
+        let connection = match self.transport {
+            #[cfg(feature = "gelf")]
+            Transport::GelfUdp => {
+                let local_address = match addresses[0] {
+                    SocketAddr::V6(_) => "[::]:0",
+                    SocketAddr::V4(_) => "0.0.0.0:0",
+                };
+                let socket = match self.options.source_ports {
+                    Some(range) => source_port::bind_udp(&addresses[0], range),
+                    None => UdpSocket::bind(local_address),
+                };
+                socket.and_then(|socket| socket.connect(&addresses[..]).map(|()| Connection::Udp(socket)))
+            },
+            _ => match self.options.source_ports {
+                Some(range) => source_port::connect_tcp(&addresses, range),
+                None => TcpStream::connect(&addresses[..]),
+            }.and_then(|socket| {
+                if self.options.flush_on_each_line {
+                    socket.set_nodelay(true)?;
+                }
+                self.tls_connection(socket)
+            }),
+        };
+        // The server may have moved, so don't keep trying the addresses it used to have
+        if connection.is_err() {
+            self.resolved = None;
+        }
+        connection
+    }
+
+    /// Resolves the server's name, or reuses the last result if it's recent enough.
+    fn resolve(&mut self) -> io::Result<Vec<SocketAddr>> {
+        if let Some((addresses, resolved_at)) = &self.resolved {
+            if resolved_at.elapsed() < self.options.dns_cache_ttl {
+                return Ok(addresses.clone());
+            }
+        }
+        let addresses: Vec<SocketAddr> = (self.destination.host.as_str(), self.port).to_socket_addrs()?.collect();
+        if addresses.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "the server's name didn't resolve to any addresses"));
+        }
+        self.resolved = Some((addresses.clone(), Instant::now()));
+        Ok(addresses)
+    }
+
+    fn tls_connection(&self, socket: TcpStream) -> io::Result<Connection> {
+        // Only for the handshake; they're lifted once it's done
+        if let Some(timeout) = self.options.handshake_timeout {
+            socket.set_read_timeout(Some(timeout))?;
+            socket.set_write_timeout(Some(timeout))?;
+        }
+        let client = ClientConnection::new(self.config.clone(), self.server_name.clone())
+            .map_err(io::Error::other)?;
+        let mut stream = StreamOwned::new(client, socket);
+        match stream.conn.complete_io(&mut stream.sock) {
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) =>
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "the TLS handshake timed out")),
+            result => result?,
+        };
+        if self.options.handshake_timeout.is_some() {
+            stream.sock.set_read_timeout(None)?;
+            stream.sock.set_write_timeout(None)?;
+        }
+        Ok(Connection::Tls(Box::new(stream)))
+    }
+
+    #[cfg(unix)]
+    fn open_inherited(&self, fd: i32) -> io::Result<Connection> {
+        use std::os::unix::io::FromRawFd;
+
+        // The fd is ours alone: nothing else in the process knows about it. Once the connection
+        // is dropped (and the fd closed), even if that's because this failed, neither `connect`
+        // nor `send` comes back for it.
+        if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        match self.transport {
+            #[cfg(feature = "gelf")]
+            Transport::GelfUdp => Ok(Connection::Udp(unsafe { UdpSocket::from_raw_fd(fd) })),
+            _ => {
+                let socket = unsafe { TcpStream::from_raw_fd(fd) };
+                if self.options.flush_on_each_line {
+                    socket.set_nodelay(true)?;
+                }
+                self.tls_connection(socket)
+            },
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn open_inherited(&self, _fd: i32) -> io::Result<Connection> {
+        Err(io::Error::other("inherited sockets are only supported on Unix"))
+    }
+
+    fn write(&mut self, frame: &str) -> io::Result<()> {
+        match self.connection.as_mut().expect("Writing without a connection.") {
+            #[cfg(feature = "gelf")]
+            Connection::Tls(stream) if self.transport == Transport::Gelf => {
+                stream.write_all(frame.as_bytes())?;
+                stream.write_all(b"\0")?;
+                if self.options.flush_on_each_line {
+                    stream.flush()?;
+                }
+            },
+            Connection::Tls(stream) => {
+                // The line break that ended the line isn't part of the message; framing takes its place
+                let message = frame.strip_suffix('\n').unwrap_or(frame);
+                match self.options.framing {
+                    Framing::OctetCounting => {
+                        stream.write_all(format!("{} {message}", message.len()).as_bytes())?;
+                    },
+                    Framing::Trailer(trailer) => {
+                        stream.write_all(message.as_bytes())?;
+                        stream.write_all(&[trailer])?;
+                    },
+                }
+                if self.options.flush_on_each_line {
+                    stream.flush()?;
+                }
+            },
+            #[cfg(feature = "gelf")]
+            Connection::Udp(socket) => match gelf::datagrams(frame.as_bytes()) {
+                Some(datagrams) => for datagram in datagrams {
+                    socket.send(&datagram)?;
+                },
+                None => eprintln!("Dropping a {} byte GELF message, which is too large even when chunked.", frame.len()),
+            },
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+// Thinning out lines while the delivery queue is backed up, per `--adaptive-shed`.
+use std::sync::atomic::Ordering;
+
+use crate::stats::{DropReason, Stats};
+
+#[derive(Debug)]
+pub struct Shedder {
+    /// Start shedding once this many lines are waiting to be delivered...
+    high_water: u64,
+    /// ...and stop once no more than this many are.
+    low_water: u64,
+    /// While shedding, forward one line in this many.
+    keep_one_in: u64,
+    shedding: bool,
+    /// Lines seen since shedding started.
+    seen: u64,
+    /// Lines shed since shedding started.
+    shed: u64,
+}
+
+impl Shedder {
+    pub fn new(high_water: u64, low_water: u64, keep_one_in: u64) -> Shedder {
+        Shedder { high_water, low_water, keep_one_in, shedding: false, seen: 0, shed: 0 }
+    }
+
+    /// Returns whether a line that just came off the queue should be forwarded, recording a
+    /// drop if not. Entering and leaving shedding mode are reported on stderr.
+    pub fn allow(&mut self, stats: &Stats) -> bool {
+        let depth = stats.queue_depth.load(Ordering::Relaxed);
+        if !self.shedding && depth >= self.high_water {
+            eprintln!("{depth} lines are waiting to be delivered; forwarding only one in {} until no more than {} are",
+                      self.keep_one_in, self.low_water);
+            self.shedding = true;
+            self.seen = 0;
+            self.shed = 0;
+        } else if self.shedding && depth <= self.low_water {
+            eprintln!("The delivery queue has drained to {depth} lines; forwarding every line again after shedding {}",
+                      self.shed);
+            self.shedding = false;
+        }
+        if !self.shedding {
+            return true;
+        }
+        self.seen += 1;
+        if (self.seen - 1).is_multiple_of(self.keep_one_in) {
+            return true;
+        }
+        self.shed += 1;
+        stats.record_drop(DropReason::Shed);
+        false
+    }
+}
@@ -0,0 +1,78 @@
+// Turning signals sent to the wrapper into messages in the log stream, per `--emit-on-signal`.
+use std::sync::mpsc;
+use std::thread;
+
+use signal_hook::iterator::Signals;
+
+use crate::DeliverValue;
+
+/// The MSGID of messages emitted for signals.
+pub const SIGNAL_MSGID: &str = "SIGNAL";
+/// The MSGID of the message saying which signal killed the command.
+pub const KILLED_MSGID: &str = "KILLED";
+
+/// Signals that can be named, without their `SIG` prefix.
+const SIGNAL_NAMES: [(&str, i32); 17] = [
+    ("HUP", libc::SIGHUP), ("INT", libc::SIGINT), ("QUIT", libc::SIGQUIT), ("TERM", libc::SIGTERM),
+    ("USR1", libc::SIGUSR1), ("USR2", libc::SIGUSR2), ("ALRM", libc::SIGALRM), ("PIPE", libc::SIGPIPE),
+    ("CONT", libc::SIGCONT), ("TSTP", libc::SIGTSTP), ("TTIN", libc::SIGTTIN), ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG), ("XCPU", libc::SIGXCPU), ("XFSZ", libc::SIGXFSZ), ("VTALRM", libc::SIGVTALRM),
+    ("WINCH", libc::SIGWINCH),
+];
+
+/// Signals that usually mean the end of a process, some of which can't be handled at all.
+const FATAL_SIGNAL_NAMES: [(&str, i32); 9] = [
+    ("KILL", libc::SIGKILL), ("STOP", libc::SIGSTOP), ("SEGV", libc::SIGSEGV), ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS), ("FPE", libc::SIGFPE), ("ILL", libc::SIGILL), ("TRAP", libc::SIGTRAP), ("SYS", libc::SIGSYS),
+];
+
+/// Names a signal for messages, as in `SIGKILL`, falling back to its number.
+pub fn name(signal: i32) -> String {
+    SIGNAL_NAMES.iter().chain(&FATAL_SIGNAL_NAMES)
+        .find(|(_, number)| *number == signal)
+        .map_or_else(|| format!("signal {signal}"), |(name, _)| format!("SIG{name}"))
+}
+
+/// One `--emit-on-signal` hook.
+#[derive(Clone, Debug)]
+pub struct SignalMessage {
+    signal: i32,
+    message: String,
+}
+
+impl SignalMessage {
+    /// Parses `SIGNAL=MESSAGE`, where SIGNAL is a name (`SIGUSR2` or `USR2`) or a number.
+    pub fn parse(value: &str) -> Result<SignalMessage, String> {
+        let (name, message) = value.split_once('=')
+            .ok_or_else(|| format!("`{value}` is not of the form SIGNAL=MESSAGE"))?;
+        let bare_name = name.to_ascii_uppercase();
+        let bare_name = bare_name.strip_prefix("SIG").unwrap_or(&bare_name);
+        let named = SIGNAL_NAMES.iter().chain(&FATAL_SIGNAL_NAMES).find(|(signal_name, _)| *signal_name == bare_name);
+        let signal = match named {
+            Some((_, signal)) => *signal,
+            None => name.parse().map_err(|_| format!("`{name}` is not a signal name or number"))?,
+        };
+        if signal_hook::consts::FORBIDDEN.contains(&signal) {
+            return Err(format!("{} can't be handled, so it can't emit a message", self::name(signal)));
+        }
+        Ok(SignalMessage { signal, message: message.into() })
+    }
+}
+
+/// Installs handlers for the hooks' signals, delivering each hook's message whenever its
+/// signal arrives.
+pub fn emit_on_signals(hooks: Vec<SignalMessage>, sender: mpsc::Sender<DeliverValue>) {
+    if hooks.is_empty() {
+        return;
+    }
+    let mut signals = Signals::new(hooks.iter().map(|hook| hook.signal))
+        .unwrap_or_else(|e| panic!("Unable to install signal handlers: {e}"));
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            for hook in hooks.iter().filter(|hook| hook.signal == signal) {
+                // Once delivery is over, there's no one to tell
+                let _ = sender.send(DeliverValue::Signal(SIGNAL_MSGID, format!("{}\n", hook.message)));
+            }
+        }
+    });
+}
@@ -0,0 +1,137 @@
+// Connecting from a local port within a given range, for networks whose firewalls only let
+// through connections from those ports, per `--source-port-range`.
+use std::io;
+#[cfg(feature = "gelf")]
+use std::net::UdpSocket;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+
+/// The local ports connections may be made from, inclusive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourcePortRange {
+    pub low: u16,
+    pub high: u16,
+}
+
+impl SourcePortRange {
+    /// Parses `LOW-HIGH`, as in `40000-40099`. A single port, as in `40000`, is a range of one.
+    pub fn parse(value: &str) -> Result<SourcePortRange, String> {
+        let (low, high) = value.split_once('-').unwrap_or((value, value));
+        let parse_port = |port: &str| port.trim().parse::<u16>().ok().filter(|port| *port > 0)
+            .ok_or_else(|| format!("`{port}` is not a port number (1-65535)"));
+        let range = SourcePortRange { low: parse_port(low)?, high: parse_port(high)? };
+        if range.low > range.high {
+            return Err(format!("`{value}` is not a port range: {} is above {}", range.low, range.high));
+        }
+        Ok(range)
+    }
+
+    fn exhausted(&self) -> io::Error {
+        io::Error::new(io::ErrorKind::AddrInUse,
+                       format!("no port in the source port range {}-{} was free to connect from", self.low, self.high))
+    }
+}
+
+/// Whether connecting from another port might work where this one didn't.
+fn port_unavailable(error: &io::Error) -> bool {
+    matches!(error.kind(), io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable)
+}
+
+/// The wildcard address of `address`'s family, with the given port.
+fn local_address(address: &SocketAddr, port: u16) -> SocketAddr {
+    match address {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, port).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, port).into(),
+    }
+}
+
+/// Connects to the first of `addresses` that accepts a connection, from the first port in
+/// `range` that's free. As with `TcpStream::connect`, the error for the last address is returned
+/// if none of them work out.
+#[cfg(unix)]
+pub fn connect_tcp(addresses: &[SocketAddr], range: SourcePortRange) -> io::Result<TcpStream> {
+    let mut last_error = range.exhausted();
+    for address in addresses {
+        for port in range.low..=range.high {
+            match sys::connect_from(address, &local_address(address, port)) {
+                Ok(socket) => return Ok(socket),
+                Err(e) if port_unavailable(&e) => last_error = range.exhausted(),
+                Err(e) => {
+                    last_error = e;
+                    break;
+                },
+            }
+        }
+    }
+    Err(last_error)
+}
+
+#[cfg(not(unix))]
+pub fn connect_tcp(_addresses: &[SocketAddr], _range: SourcePortRange) -> io::Result<TcpStream> {
+    Err(io::Error::other("source port ranges are only supported on Unix"))
+}
+
+/// Binds a UDP socket to the first port in `range` that's free, for sending to `address`.
+#[cfg(feature = "gelf")]
+pub fn bind_udp(address: &SocketAddr, range: SourcePortRange) -> io::Result<UdpSocket> {
+    for port in range.low..=range.high {
+        match UdpSocket::bind(local_address(address, port)) {
+            Err(e) if port_unavailable(&e) => continue,
+            result => return result,
+        }
+    }
+    Err(range.exhausted())
+}
+
+/// The standard library can't bind a TCP socket before connecting it, so this goes to libc.
+#[cfg(unix)]
+mod sys {
+    use std::io;
+    use std::net::{SocketAddr, TcpStream};
+    use std::os::unix::io::FromRawFd;
+
+    pub fn connect_from(address: &SocketAddr, local: &SocketAddr) -> io::Result<TcpStream> {
+        let family = match address {
+            SocketAddr::V4(_) => libc::AF_INET,
+            SocketAddr::V6(_) => libc::AF_INET6,
+        };
+        let fd = unsafe { libc::socket(family, libc::SOCK_STREAM, 0) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // Owning the fd from here on closes it on the way out, whatever happens
+        let socket = unsafe { TcpStream::from_raw_fd(fd) };
+        if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        with_sockaddr(local, |addr, len| unsafe { libc::bind(fd, addr, len) })?;
+        with_sockaddr(address, |addr, len| unsafe { libc::connect(fd, addr, len) })?;
+        Ok(socket)
+    }
+
+    /// Calls `f` with `address` as a C `sockaddr`, turning a -1 result into the OS error.
+    fn with_sockaddr(address: &SocketAddr,
+                     f: impl FnOnce(*const libc::sockaddr, libc::socklen_t) -> libc::c_int) -> io::Result<()> {
+        let result = match address {
+            SocketAddr::V4(address) => {
+                let mut sockaddr: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+                sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+                sockaddr.sin_port = address.port().to_be();
+                sockaddr.sin_addr = libc::in_addr { s_addr: u32::from_ne_bytes(address.ip().octets()) };
+                f(&sockaddr as *const _ as *const libc::sockaddr, std::mem::size_of_val(&sockaddr) as libc::socklen_t)
+            },
+            SocketAddr::V6(address) => {
+                let mut sockaddr: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+                sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sockaddr.sin6_port = address.port().to_be();
+                sockaddr.sin6_addr = libc::in6_addr { s6_addr: address.ip().octets() };
+                sockaddr.sin6_flowinfo = address.flowinfo();
+                sockaddr.sin6_scope_id = address.scope_id();
+                f(&sockaddr as *const _ as *const libc::sockaddr, std::mem::size_of_val(&sockaddr) as libc::socklen_t)
+            },
+        };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
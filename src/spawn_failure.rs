@@ -0,0 +1,58 @@
+// Explaining why the command couldn't be found, for the error printed when it fails to start.
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the command was looked for.
+pub struct Lookup<'a> {
+    /// The program to run, as given.
+    pub command: &'a OsStr,
+    /// The command's --workdir, if any.
+    pub workdir: Option<&'a Path>,
+    /// The PATH the command was looked up on: the one given with --env, or else the wrapper's
+    /// own, unless --env-clear removed it.
+    pub path: Option<&'a OsStr>,
+}
+
+/// Describes how `lookup.command` was resolved, for when spawning it failed with `error`. Only
+/// a missing command gets an explanation; for other errors the message already says it all.
+pub fn explain(lookup: &Lookup, error: &io::Error) -> Option<String> {
+    if error.kind() != io::ErrorKind::NotFound {
+        return None;
+    }
+    let cwd = match lookup.workdir {
+        Some(workdir) if !workdir.is_dir() => {
+            return Some(format!("The working directory {workdir:?} (from --workdir) does not exist."));
+        },
+        Some(workdir) => std::env::current_dir().map(|cwd| cwd.join(workdir)).unwrap_or_else(|_| workdir.into()),
+        None => std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+    };
+    let command = Path::new(lookup.command);
+    let lines = if lookup.command.to_string_lossy().contains('/') {
+        let mut lines = vec![format!(
+            "The command contains a slash, so it was run as a path (relative to {cwd:?}) rather than looked up on PATH."
+        )];
+        if cwd.join(command).is_file() {
+            // As with a script whose #! line names a missing interpreter
+            lines.push("The file exists, so it may be its interpreter or loader that is missing.".to_string());
+        }
+        lines
+    } else {
+        match lookup.path {
+            Some(path) if !path.is_empty() => {
+                let directories: Vec<String> = std::env::split_paths(path)
+                    .map(|directory| format!("{directory:?}"))
+                    .collect();
+                vec![
+                    format!("It was looked up on PATH, in {}, and found in none of them.", directories.join(", ")),
+                    format!("The working directory was {cwd:?}, which any relative PATH entries are relative to."),
+                ]
+            },
+            _ => vec![format!(
+                "PATH is not set for the command, so it was looked up on the system's default search path. \
+                 The working directory was {cwd:?}."
+            )],
+        }
+    };
+    Some(lines.join("\n"))
+}
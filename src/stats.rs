@@ -0,0 +1,121 @@
+// Counters describing what happened to the lines we captured, shared between threads.
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Why a line was deliberately not forwarded.
+// Recorded by the throttling features (--rate-limit and --adaptive-shed) as they discard lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    RateLimit,
+    Shed,
+}
+
+impl DropReason {
+    pub const ALL: [DropReason; 2] = [DropReason::RateLimit, DropReason::Shed];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DropReason::RateLimit => "rate-limit",
+            DropReason::Shed => "adaptive-shed",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Stats {
+    /// Lines read from the command, whatever became of them.
+    pub lines_read: AtomicU64,
+    /// The size of those lines as the command wrote them, line breaks included.
+    pub bytes_read: AtomicU64,
+    /// Lines that made it to the primary server, not counting any re-sent after a reconnect.
+    pub lines_forwarded: AtomicU64,
+    /// Lines withheld by `--dedup` because they repeated the previous line.
+    pub duplicates_suppressed: AtomicU64,
+    /// Matches replaced by `--redact` rules.
+    pub redactions: AtomicU64,
+    /// Lines read from the command that the delivery thread hasn't picked up yet.
+    pub queue_depth: AtomicU64,
+    /// Connections to a destination that were lost and re-established.
+    pub reconnects: AtomicU64,
+    /// Lines dropped, indexed by `DropReason`.
+    drops: [AtomicU64; DropReason::ALL.len()],
+    /// Lines dropped by a keyed `--rate-limit`, by key.
+    keyed_drops: Mutex<BTreeMap<String, u64>>,
+}
+
+/// Where drops for keys beyond the tracked maximum are counted.
+const OTHER_KEYS: &str = "(other)";
+
+impl Stats {
+    pub fn record_drop(&self, reason: DropReason) {
+        self.drops[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a rate-limit drop against its key. At most `max_keys` keys are tracked; drops for
+    /// any others are lumped together.
+    pub fn record_keyed_drop(&self, key: &str, max_keys: usize) {
+        let mut keyed_drops = self.keyed_drops.lock().unwrap();
+        let key = if keyed_drops.contains_key(key) || keyed_drops.len() < max_keys { key } else { OTHER_KEYS };
+        *keyed_drops.entry(key.to_string()).or_default() += 1;
+    }
+
+    pub fn keyed_drops(&self) -> BTreeMap<String, u64> {
+        self.keyed_drops.lock().unwrap().clone()
+    }
+
+    pub fn drops(&self, reason: DropReason) -> u64 {
+        self.drops[reason as usize].load(Ordering::Relaxed)
+    }
+}
+
+/// Produces the periodic `--drop-warn-interval` summary of lines dropped since the last one.
+#[derive(Debug)]
+pub struct DropWarner {
+    interval_secs: u64,
+    reported: [u64; DropReason::ALL.len()],
+    reported_by_key: HashMap<String, u64>,
+}
+
+impl DropWarner {
+    pub fn new(interval_secs: u64) -> DropWarner {
+        DropWarner { interval_secs, reported: Default::default(), reported_by_key: HashMap::new() }
+    }
+
+    /// Returns the summary line, or `None` if nothing was dropped since the last call.
+    pub fn summary(&mut self, stats: &Stats) -> Option<String> {
+        let mut total = 0;
+        let mut by_reason = vec![];
+        for reason in DropReason::ALL {
+            let drops = stats.drops(reason);
+            let new_drops = drops - self.reported[reason as usize];
+            self.reported[reason as usize] = drops;
+            if new_drops > 0 {
+                total += new_drops;
+                by_reason.push(format!("{}: {new_drops}{}", reason.name(), self.key_breakdown(stats, reason)));
+            }
+        }
+        if total == 0 {
+            return None;
+        }
+        Some(format!("dropped {total} messages in last {}s ({})", self.interval_secs, by_reason.join(", ")))
+    }
+
+    /// For a keyed rate limit, the ` [KEY: N, ...]` breakdown of new rate-limit drops by key.
+    fn key_breakdown(&mut self, stats: &Stats, reason: DropReason) -> String {
+        if reason != DropReason::RateLimit {
+            return String::new();
+        }
+        let mut by_key = vec![];
+        for (key, drops) in stats.keyed_drops() {
+            let reported = self.reported_by_key.insert(key.clone(), drops).unwrap_or(0);
+            if drops > reported {
+                by_key.push(format!("{key}: {}", drops - reported));
+            }
+        }
+        if by_key.is_empty() {
+            return String::new();
+        }
+        format!(" [{}]", by_key.join(", "))
+    }
+}
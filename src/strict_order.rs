@@ -0,0 +1,81 @@
+// Reading the command's stdout and stderr from one thread, in the order the writes arrive, per
+// `--strict-order`.
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+use std::process::{ChildStderr, ChildStdout};
+
+/// One of the command's output streams, and the start of a line read from it that hasn't ended
+/// yet.
+struct Pipe<R> {
+    reader: Option<R>,
+    name: &'static str,
+    partial: Vec<u8>,
+}
+
+impl<R: Read + AsRawFd> Pipe<R> {
+    fn new(reader: R, name: &'static str) -> Pipe<R> {
+        Pipe { reader: Some(reader), name, partial: vec![] }
+    }
+
+    fn poll_fd(&self) -> libc::pollfd {
+        // A negative fd is ignored by poll(2), which is how a closed stream drops out
+        let fd = self.reader.as_ref().map_or(-1, AsRawFd::as_raw_fd);
+        libc::pollfd { fd, events: libc::POLLIN, revents: 0 }
+    }
+
+    /// Reads whatever is available, handing each line completed by it to `on_line`. At the end
+    /// of the stream, a last line without a line break is handed over too.
+    fn read(&mut self, buffer: &mut [u8], on_line: &mut impl FnMut(Vec<u8>, &'static str) -> bool) -> io::Result<()> {
+        let Some(reader) = self.reader.as_mut() else { return Ok(()) };
+        let len = match reader.read(buffer) {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => return Ok(()),
+            result => result?,
+        };
+        if len == 0 {
+            self.reader = None;
+            if !self.partial.is_empty() {
+                on_line(std::mem::take(&mut self.partial), self.name);
+            }
+            return Ok(());
+        }
+        let mut rest = &buffer[..len];
+        while let Some(end) = rest.iter().position(|&byte| byte == b'\n') {
+            self.partial.extend_from_slice(&rest[..=end]);
+            rest = &rest[end + 1..];
+            if !on_line(std::mem::take(&mut self.partial), self.name) {
+                self.reader = None;
+                return Ok(());
+            }
+        }
+        self.partial.extend_from_slice(rest);
+        Ok(())
+    }
+}
+
+/// Reads lines from both of the command's output streams until they close, handing each one to
+/// `on_line` along with the name of its stream, as soon as it's been written. `on_line` returns
+/// whether to keep going.
+pub fn read_both(stdout: ChildStdout, stderr: ChildStderr,
+                 mut on_line: impl FnMut(Vec<u8>, &'static str) -> bool) -> io::Result<()> {
+    let mut stdout = Pipe::new(stdout, "stdout");
+    let mut stderr = Pipe::new(stderr, "stderr");
+    let mut buffer = [0; 8192];
+    while stdout.reader.is_some() || stderr.reader.is_some() {
+        let mut fds = [stdout.poll_fd(), stderr.poll_fd()];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(error);
+        }
+        // A stream that has closed is readable too, and reading it then finds the end
+        if fds[0].revents != 0 {
+            stdout.read(&mut buffer, &mut on_line)?;
+        }
+        if fds[1].revents != 0 {
+            stderr.read(&mut buffer, &mut on_line)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,37 @@
+// The message describing the whole run, emitted last per `--run-summary`.
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::format::SdElement;
+use crate::stats::{DropReason, Stats};
+
+/// The MSGID of the run summary.
+pub const SUMMARY_MSGID: &str = "SUMMARY";
+
+/// Builds the summary's SD-ELEMENT and MSG. `exit_code` is the command's, as the wrapper
+/// reports it, if it's known.
+pub fn summary(stats: &Stats, duration: Duration, exit_code: Option<i32>) -> (SdElement, String) {
+    let lines_read = stats.lines_read.load(Ordering::Relaxed);
+    let duplicates = stats.duplicates_suppressed.load(Ordering::Relaxed);
+    let lines_forwarded = stats.lines_forwarded.load(Ordering::Relaxed);
+    let mut element = SdElement::new("summary@32473")
+        .param("lines-read", lines_read)
+        .param("lines-forwarded", lines_forwarded)
+        .param("bytes-read", stats.bytes_read.load(Ordering::Relaxed))
+        .param("duration-ms", duration.as_millis())
+        .param("reconnects", stats.reconnects.load(Ordering::Relaxed))
+        .param("duplicates-suppressed", duplicates)
+        .param("redactions", stats.redactions.load(Ordering::Relaxed));
+    for reason in DropReason::ALL {
+        element = element.param(&format!("drops-{}", reason.name()), stats.drops(reason));
+    }
+    let outcome = match exit_code {
+        Some(code) => {
+            element = element.param("exit-code", code);
+            format!("the command exited with code {code}")
+        },
+        None => "the command's exit code is unknown".to_string(),
+    };
+    let msg = format!("Forwarded {lines_forwarded} of {lines_read} lines in {:.3}s; {outcome}\n", duration.as_secs_f64());
+    (element, msg)
+}
@@ -0,0 +1,67 @@
+// A minimal TLS syslog receiver, per the hidden `--test-server` option, for the tests and for
+// checking connectivity without a real collector.
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+
+use rustls::{Certificate, PrivateKey, ServerConfig, ServerConnection, StreamOwned};
+
+// A certificate for localhost (and 127.0.0.1 and ::1), signed by the CA in `certs/ca.crt`,
+// which is what a client needs to trust. Both are good for a hundred years.
+const CERTIFICATE: &str = include_str!("../certs/localhost.crt");
+const CA_CERTIFICATE: &str = include_str!("../certs/ca.crt");
+const PRIVATE_KEY: &str = include_str!("../certs/localhost.key");
+
+/// Accepts one connection on `port`, printing everything received on it to stdout verbatim,
+/// then exits. Problems with the connection are printed too, as `ERROR: ...` lines.
+pub fn run(port: u16) -> ! {
+    let config = Arc::new(server_config());
+    // On most systems, the IPv6 wildcard address accepts IPv4 connections too
+    let listener = TcpListener::bind(("::", port)).or_else(|_| TcpListener::bind(("0.0.0.0", port)))
+        .unwrap_or_else(|e| {
+            eprintln!("Unable to listen on port {port}: {e}");
+            std::process::exit(40);
+        });
+    println!("Listening on port {port}");
+
+    let result = listener.accept()
+        .and_then(|(socket, _)| serve(socket, config));
+    if let Err(e) = result {
+        println!("ERROR: {e}");
+        std::process::exit(1);
+    }
+    std::process::exit(0);
+}
+
+fn server_config() -> ServerConfig {
+    let certificates = [CERTIFICATE, CA_CERTIFICATE].iter()
+        .flat_map(|pem| rustls_pemfile::certs(&mut pem.as_bytes()).expect("The bundled certificates are valid PEM."))
+        .map(Certificate)
+        .collect();
+    let key = rustls_pemfile::pkcs8_private_keys(&mut PRIVATE_KEY.as_bytes())
+        .expect("The bundled private key is valid PEM.")
+        .remove(0);
+    ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certificates, PrivateKey(key))
+        .expect("The bundled certificate matches the bundled private key.")
+}
+
+/// Copies what the client sends to stdout until it ends the connection, then ends it too.
+fn serve(socket: TcpStream, config: Arc<ServerConfig>) -> io::Result<()> {
+    let connection = ServerConnection::new(config).map_err(io::Error::other)?;
+    let mut stream = StreamOwned::new(connection, socket);
+    let mut stdout = io::stdout();
+    let mut buffer = [0; 4096];
+    loop {
+        let len = stream.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        stdout.write_all(&buffer[..len])?;
+        stdout.flush()?;
+    }
+    stream.conn.send_close_notify();
+    stream.flush()
+}
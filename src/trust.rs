@@ -0,0 +1,74 @@
+// Assembling the set of certificate authorities we trust when connecting to the syslog server.
+use std::fs;
+use std::path::Path;
+
+use rustls::{Certificate, OwnedTrustAnchor, RootCertStore};
+use rustls_pemfile::Item;
+
+/// Builds the trust store: the bundled webpki roots, plus any extra certificates supplied
+/// as a PEM file and/or as inline PEM text.
+pub fn build_root_store(certificates_file: Option<&Path>, certificates_pem: Option<&str>) -> Result<RootCertStore, String> {
+    let mut root_store = RootCertStore::empty();
+
+    if let Some(trusted_certificates_file) = certificates_file {
+        let pem = fs::read(trusted_certificates_file)
+            .map_err(|e| format!("Could not open trusted certificate file `{trusted_certificates_file:?}`: {e}."))?;
+        let source = format!("The trusted certificate file `{trusted_certificates_file:?}`");
+        add_certificates(&mut root_store, &pem, &source)?;
+    }
+
+    if let Some(pem) = certificates_pem {
+        let pem = unescape_inline_pem(pem);
+        add_certificates(&mut root_store, pem.as_bytes(), "The inline trusted certificate PEM")?;
+    }
+
+    root_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+
+    Ok(root_store)
+}
+
+/// Adds every PEM-encoded X509 certificate in `pem` to the store. `source` names where the
+/// PEM came from in the resulting error messages.
+fn add_certificates(root_store: &mut RootCertStore, pem: &[u8], source: &str) -> Result<(), String> {
+    if pem.iter().all(u8::is_ascii_whitespace) {
+        return Err(format!("{source} is empty, so it did not contain a parseable certificate."));
+    }
+    let items = rustls_pemfile::read_all(&mut &pem[..])
+        .map_err(|e| format!("Could not parse trusted certificate from {source}: {e}"))?;
+    let has_private_key = items.iter().any(|item| matches!(item, Item::RSAKey(_) | Item::PKCS8Key(_) | Item::ECKey(_)));
+    let certificates: Vec<Vec<u8>> = items.into_iter().filter_map(|item| match item {
+        Item::X509Certificate(certificate) => Some(certificate),
+        _ => None,
+    }).collect();
+    if certificates.is_empty() && has_private_key {
+        return Err(format!("{source} looks like a private key, not a certificate. Give the certificate of the \
+                            authority that signed the server's certificate instead."));
+    }
+    if certificates.is_empty() {
+        return Err(format!("{source} did not contain a parseable certificate."));
+    }
+    for certificate in certificates {
+        root_store
+            .add(&Certificate(certificate))
+            .map_err(|e| format!("Could not add trusted certificate from {source}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// PEM that has been stuffed into an environment variable often arrives with its line breaks
+/// escaped as literal `\n` (or `\r\n`) sequences, and sometimes still wrapped in the quotes
+/// from an env file. The PEM parser needs real line breaks, so undo both.
+fn unescape_inline_pem(pem: &str) -> String {
+    let pem = pem.trim();
+    let pem = pem
+        .strip_prefix('"')
+        .and_then(|p| p.strip_suffix('"'))
+        .unwrap_or(pem);
+    pem.replace("\\r\\n", "\n").replace("\\n", "\n")
+}
@@ -1,28 +1,72 @@
 extern crate assert_cli;
-use assert_cli::Assert;
+use assert_cli::{Assert, Environment};
 
+use base64::Engine;
+use std::io::{BufRead, BufReader, Read};
 use std::net::TcpListener;
-use std::process::{Command, Stdio, Child};
+use std::process::{Command, Stdio, Child, ChildStdout};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use syslog_wrapper::sender::{Destination, Framing, Options, Sender, Transport};
+use syslog_wrapper::stats::Stats;
+
+mod support;
+use support::Frame;
+
+/// The wrapper as built for the tests, with the `test-server` feature. `cargo run` would build
+/// it again without.
+fn wrapper() -> Assert {
+  Assert::command(&[env!("CARGO_BIN_EXE_syslog-wrapper")])
+}
 
 #[test]
 fn it_suggests_help() {
-    Assert::main_binary()
+    wrapper()
       .fails_with(2).and()
       .stderr().contains("try --help").unwrap();
 }
 
+#[test]
+fn it_points_out_wrapper_options_given_after_the_separator() {
+  wrapper()
+    .with_args(&["localhost", "--", "--hostname=web-1", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("did you mean to put --hostname before --?").unwrap();
+  wrapper()
+    .with_args(&["--", "--server", "foo", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("did you mean to put --server before --?").unwrap();
+  wrapper()
+    .with_env(Environment::inherit().insert("SYSLOG_SERVER", "localhost"))
+    .with_args(&["--", "--server", "foo", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("did you mean to put --server before --?").unwrap();
+}
+
 #[test]
 fn it_fails_to_locate_binary() {
-  Assert::main_binary()
+  wrapper()
       .with_args(&["localhost", "--", "./notreal.sh"])
       .fails_with(40).and()
       .stderr().contains("No such file or directory").unwrap();
 }
 
+#[test]
+fn it_explains_where_it_looked_for_a_missing_binary() {
+  wrapper()
+      .with_args(&["localhost", "--env", "PATH=/nonexistent/bin", "--", "notreal"])
+      .fails_with(40).and()
+      .stderr().contains(r#"It was looked up on PATH, in "/nonexistent/bin", and found in none of them."#).unwrap();
+  wrapper()
+      .with_args(&["localhost", "--", "./notreal.sh"])
+      .fails_with(40).and()
+      .stderr().contains("contains a slash, so it was run as a path").unwrap();
+}
+
 #[test]
 fn it_fails_to_connect() {
-  Assert::main_binary()
+  wrapper()
     .with_args(&["localhost", "--", "ls"])
       .fails_with(127).and()
       .stderr().contains("Unable to connect").unwrap();
@@ -32,77 +76,1468 @@ fn it_fails_to_connect() {
 fn it_preserves_exit_code() {
   let (mut server, test_flags) = spawn_test_server();
 
-  Assert::main_binary()
+  wrapper()
     .with_args(&test_flags)
     .with_args(&["--", "sh", "-c", "exit 69"])
       .fails_with(69).and().unwrap();
 
-  server.kill().unwrap();
+  server.process.kill().unwrap();
 }
 
 #[test]
 fn it_connects_and_sends_several_lines() {
-  let (mut server, test_flags) = spawn_test_server();
+  let (server, test_flags) = spawn_test_server();
 
-  Assert::main_binary()
+  wrapper()
     .with_args(&test_flags)
     .with_args(&["--", "seq", "1", "5"])
     .unwrap();
 
-  server.kill().unwrap();
-  let output = server.wait_with_output().expect("Not able to capture test server output.");
-  let output_string = String::from_utf8(output.stdout).unwrap();
-  let output_lines: Vec<String> = output_string.lines().filter_map(|line| {
-    if line.starts_with("<") {
-      // Remove the leading syslog elements
-      return Some(line.split(" ").skip(7).collect::<Vec<&str>>().join(" "));
-    } else {
-      return Option::None;
-    }  }).collect();
-  assert_eq!(vec!["1", "2", "3", "4", "5"], output_lines);
+  let frames = server.received_frames();
+  assert_eq!(vec!["1", "2", "3", "4", "5"], frames.iter().map(|frame| frame.msg.as_str()).collect::<Vec<_>>());
+  for frame in &frames {
+    assert_eq!((2, 6), (frame.facility(), frame.severity()));
+    assert_eq!("1", frame.version);
+    assert_eq!("seq", frame.appname);
+    assert_eq!(("-", "-", "-"), (frame.procid.as_str(), frame.msgid.as_str(), frame.sd.as_str()));
+  }
+}
+
+#[test]
+fn it_delivers_everything_from_a_command_that_exits_immediately() {
+  let (server, test_flags) = spawn_test_server();
+
+  // The command is long gone before the TLS handshake even starts
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--", "echo", "fast"])
+    .unwrap();
+
+  let output = server.received_output();
+  let frames = parse_frames(output.as_bytes());
+  assert_eq!(1, frames.len());
+  assert_eq!("fast", Frame::parse(&frames[0]).msg);
+  assert!(!output.contains("ERROR"), "The connection wasn't closed cleanly: {output}");
+}
+
+#[test]
+fn it_frames_messages_with_octet_counting_by_default() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--", "sh", "-c", "echo one; echo two; sleep 0.2"])
+    .unwrap();
+
+  let output = server.received_output();
+  let frames = parse_frames(output.as_bytes());
+  assert_eq!(2, frames.len());
+  for frame in frames {
+    assert!(output.contains(&format!("{} {frame}", frame.len())), "Unexpected output `{output}`");
+  }
+}
+
+#[test]
+fn it_frames_messages_with_line_feeds() {
+  assert_trailer_framing("lf", "\n");
+}
+
+#[test]
+fn it_frames_messages_with_nul_bytes() {
+  assert_trailer_framing("nul", "\0");
+}
+
+#[test]
+fn it_frames_messages_with_a_custom_trailer() {
+  assert_trailer_framing("trailer:0x1e", "\x1e");
+}
+
+#[test]
+fn it_rejects_unknown_framings() {
+  wrapper()
+    .with_args(&["localhost", "--framing", "trailer:toolong", "--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("`toolong` is not a byte").unwrap();
+}
+
+fn assert_trailer_framing(framing: &str, trailer: &str) {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--framing", framing, "--", "sh", "-c", "echo one; echo two; sleep 0.2"])
+    .unwrap();
+
+  let output = server.received_output();
+  assert!(output.contains(&format!(" - - - one{trailer}<22>1 ")), "Unexpected output `{output:?}`");
+  assert!(output.contains(&format!(" - - - two{trailer}")), "Unexpected output `{output:?}`");
+}
+
+#[test]
+fn it_strips_carriage_returns_from_crlf_line_endings() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--", "sh", "-c", "printf 'one\\r\\ntwo\\r\\n'; sleep 0.2"])
+    .unwrap();
+
+  assert_eq!(vec!["one", "two"], server.received_messages());
+}
+
+#[test]
+fn it_trusts_inline_pem_certificates() {
+  let (server, server_address) = spawn_untrusted_test_server();
+  let pem = std::fs::read_to_string("certs/ca.crt").expect("Unable to read test CA certificate.");
+
+  wrapper()
+    .with_env(Environment::inherit().insert("SYSLOG_CA_PEM", pem))
+    .with_args(&[server_address.as_str(), "--", "echo", "trusted"])
+    .unwrap();
+
+  assert_eq!(vec!["trusted"], server.received_messages());
+}
+
+#[test]
+fn it_trusts_inline_pem_certificates_with_escaped_newlines() {
+  let (server, server_address) = spawn_untrusted_test_server();
+  let pem = std::fs::read_to_string("certs/ca.crt").expect("Unable to read test CA certificate.");
+  let escaped_pem = pem.replace('\n', "\\n");
+
+  wrapper()
+    .with_args(&[server_address.as_str(), "--add-trusted-certificates-pem", escaped_pem.as_str(),
+                 "--", "echo", "trusted"])
+    .unwrap();
+
+  assert_eq!(vec!["trusted"], server.received_messages());
+}
+
+#[test]
+fn it_rejects_inline_pem_without_certificates() {
+  wrapper()
+    .with_args(&["localhost", "--add-trusted-certificates-pem", "not a certificate", "--", "ls"])
+    .fails().and()
+    .stderr().contains("did not contain a parseable certificate").unwrap();
+}
+
+#[test]
+fn it_rejects_an_empty_certificate_file() {
+  let empty_file = std::env::temp_dir().join(format!("syslog-wrapper-test-{}.crt", std::process::id()));
+  std::fs::write(&empty_file, " \n\n").unwrap();
+
+  wrapper()
+    .with_args(&["localhost", "--add-trusted-certificates", empty_file.to_str().unwrap(), "--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("is empty, so it did not contain a parseable certificate").unwrap();
+  std::fs::remove_file(&empty_file).unwrap();
+}
+
+#[test]
+fn it_rejects_a_private_key_given_as_a_certificate() {
+  wrapper()
+    .with_args(&["localhost", "--add-trusted-certificates", "certs/localhost.key", "--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("looks like a private key, not a certificate").unwrap();
+}
+
+#[test]
+fn it_validates_the_configuration_without_running_anything() {
+  wrapper()
+    .with_args(&["localhost", "--add-trusted-certificates", "certs/ca.crt", "--validate-config"])
+    .stdout().contains("The configuration is valid.").unwrap();
+}
+
+#[test]
+fn it_rejects_an_invalid_server_name_before_running_the_command() {
+  let marker = std::env::temp_dir().join(format!("syslog-wrapper-test-{}.named", std::process::id()));
+  let marker = marker.to_str().unwrap();
+
+  wrapper()
+    .with_args(&["bad_name!", "--validate-config"])
+      .fails_with(2).and()
+      .stderr().contains("`bad_name!` is not a valid server name").unwrap();
+  wrapper()
+    .with_args(&["localhost", "--also-server", "bad_name!:6514", "--", "touch", marker])
+      .fails_with(2).and()
+      .stderr().contains("`bad_name!` is not a valid server name").unwrap();
+  assert!(!std::path::Path::new(marker).exists(), "The command should not have been started.");
+}
+
+#[test]
+fn it_rejects_a_missing_certificate_file_before_running_the_command() {
+  let marker = std::env::temp_dir().join(format!("syslog-wrapper-test-{}.ran", std::process::id()));
+  let marker = marker.to_str().unwrap();
+
+  wrapper()
+    .with_args(&["localhost", "--add-trusted-certificates", "no-such-file.crt", "--", "touch", marker])
+      .fails_with(2).and()
+      .stderr().contains("Could not open trusted certificate file").unwrap();
+  assert!(!std::path::Path::new(marker).exists(), "The command should not have been started.");
+}
+
+#[test]
+fn it_coalesces_duplicate_lines() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--dedup", "--", "sh", "-c", "echo a; echo a; echo a; echo b; echo b; sleep 0.2"])
+    .unwrap();
+
+  assert_eq!(vec!["a", "last message repeated 2 times", "b", "last message repeated 1 times"],
+             server.received_messages());
+}
+
+#[test]
+fn it_flushes_duplicate_summaries_after_an_interval() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--dedup", "--dedup-flush-interval", "1",
+                 "--", "sh", "-c", "echo a; echo a; sleep 2; echo a; echo b; sleep 0.2"])
+    .unwrap();
+
+  assert_eq!(vec!["a", "last message repeated 1 times", "last message repeated 1 times", "b"],
+             server.received_messages());
+}
+
+#[test]
+fn it_flushes_on_each_line() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--flush-on-each-line", "--", "sh", "-c", "echo 1; echo 2; echo 3; sleep 0.2"])
+    .unwrap();
+
+  assert_eq!(vec!["1", "2", "3"], server.received_messages());
+}
+
+#[test]
+fn it_sends_an_audit_copy_with_its_own_priority() {
+  let (primary, test_flags) = spawn_test_server();
+  let (audit, audit_address) = spawn_untrusted_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--also-server", &format!("{audit_address},facility=local5,severity=notice"),
+                 "--", "sh", "-c", "echo one; echo two; sleep 0.2"])
+    .unwrap();
+
+  let primary_frames = primary.received_frames();
+  let audit_frames = audit.received_frames();
+  assert_eq!(vec![22, 22], primary_frames.iter().map(|frame| frame.pri).collect::<Vec<_>>());
+  assert_eq!(vec![173, 173], audit_frames.iter().map(|frame| frame.pri).collect::<Vec<_>>());
+  // Apart from the PRI, the copies are identical
+  let without_pri = |frames: &[Frame]| -> Vec<Frame> {
+    frames.iter().map(|frame| Frame { pri: 0, ..frame.clone() }).collect()
+  };
+  assert_eq!(without_pri(&primary_frames), without_pri(&audit_frames));
+}
+
+#[test]
+fn it_carries_on_without_an_unreachable_also_server() {
+  let listener = TcpListener::bind("localhost:0").expect("Unable to pick a port.");
+  let closed_address = format!("localhost:{}", listener.local_addr().unwrap().port());
+  drop(listener);
+
+  let (primary, test_flags) = spawn_test_server();
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--also-server", &closed_address, "--connect-retries-are-fatal-after", "0",
+                 "--", "sh", "-c", "echo one; echo two; sleep 0.2"])
+    .stderr().contains("Carrying on without that --also-server.")
+    .unwrap();
+  assert_eq!(vec!["one", "two"], primary.received_messages());
+
+  // Nor one that goes away later on
+  let (primary, test_flags) = spawn_test_server();
+  let (mut secondary, secondary_address) = spawn_untrusted_test_server();
+  let wrapper = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+    .args(&test_flags)
+    .args(["--also-server", &secondary_address, "--max-retries", "1", "--min-reconnect-interval", "200"])
+    .args(["--", "sh", "-c", "echo one; sleep 1; echo two; sleep 0.5; echo three; sleep 0.5; echo four; sleep 0.2"])
+    .stdout(Stdio::null())
+    .stderr(Stdio::piped())
+    .spawn()
+    .expect("Unable to spawn syslog-wrapper.");
+  secondary.wait_for_message("one");
+  secondary.process.kill().unwrap();
+  secondary.process.wait().unwrap();
+
+  let output = wrapper.wait_with_output().unwrap();
+  let stderr = String::from_utf8(output.stderr).unwrap();
+  assert!(output.status.success(), "Unexpected stderr: {stderr}");
+  assert!(stderr.contains(&format!("Carrying on without `{secondary_address}`")), "Unexpected stderr: {stderr}");
+  assert_eq!(vec!["one", "two", "three", "four"], primary.received_messages());
+}
+
+#[test]
+fn it_rejects_unknown_facilities() {
+  wrapper()
+    .with_args(&["localhost", "--also-server", "localhost,facility=local9", "--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("is not a syslog facility").unwrap();
+}
+
+#[test]
+fn it_sends_messages_with_the_given_priority() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--priority", "local3.warning", "--", "echo", "hi"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(vec![156], frames.iter().map(|frame| frame.pri).collect::<Vec<_>>());
+
+  wrapper()
+    .with_args(&["localhost", "--priority", "local3.loud", "--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("`loud` is not a syslog severity").unwrap();
+}
+
+#[test]
+fn it_verifies_certificates_for_ip_addresses() {
+  let (server, test_flags) = spawn_test_server();
+  let port = test_flags[0].rsplit_once(':').unwrap().1;
+
+  wrapper()
+    .with_args(&[&format!("127.0.0.1:{port}"), "--add-trusted-certificates", "certs/ca.crt"])
+    .with_args(&["--", "echo", "by address"])
+    .unwrap();
+
+  assert_eq!(vec!["by address"], server.received_messages());
+}
+
+#[test]
+fn it_stays_quiet_about_drops_when_nothing_was_dropped() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--drop-warn-interval", "1", "--", "sh", "-c", "echo one; sleep 1.5; echo two"])
+      .stderr().doesnt_contain("dropped").unwrap();
+
+  assert_eq!(vec!["one", "two"], server.received_messages());
+}
+
+#[cfg(feature = "gelf")]
+#[test]
+fn it_sends_gelf_documents_over_tls() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--transport", "gelf", "--hostname", "precarious-tuft-41", "--appname", "cobbler",
+                 "--", "sh", "-c", "echo one; echo two; sleep 0.2"])
+    .unwrap();
+
+  // Documents are null-terminated; the first is preceded by the test server's own output
+  let output = server.received_output();
+  let documents: Vec<serde_json::Value> = output.split('\0').filter_map(|piece| {
+    piece.find('{').and_then(|start| serde_json::from_str(&piece[start..]).ok())
+  }).collect();
+  assert_eq!(2, documents.len());
+  assert_eq!("one", documents[0]["short_message"]);
+  assert_eq!("two", documents[1]["short_message"]);
+  assert_eq!("1.1", documents[0]["version"]);
+  assert_eq!("precarious-tuft-41", documents[0]["host"]);
+  assert_eq!("cobbler", documents[0]["_appname"]);
+  assert_eq!(6, documents[0]["level"]);
+  assert!(documents[0]["timestamp"].as_f64().is_some());
+}
+
+#[cfg(feature = "gelf")]
+#[test]
+fn it_chunks_long_gelf_documents_over_udp() {
+  let socket = std::net::UdpSocket::bind("127.0.0.1:0").expect("Unable to bind a UDP socket.");
+  socket.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+  let address = socket.local_addr().unwrap().to_string();
+  let long_line = "x".repeat(20_000);
+
+  wrapper()
+    .with_args(&[address.as_str(), "--transport", "gelf-udp", "--", "echo", long_line.as_str()])
+    .unwrap();
+
+  // Reassemble the chunks, which carry a 12 byte header: magic, message ID, sequence number, count
+  let mut chunks: Vec<(u8, Vec<u8>)> = vec![];
+  let mut buffer = [0; 65536];
+  loop {
+    let len = socket.recv(&mut buffer).expect("Did not receive all GELF chunks.");
+    assert_eq!([0x1e, 0x0f], buffer[..2]);
+    chunks.push((buffer[10], buffer[12..len].to_vec()));
+    if chunks.len() == usize::from(buffer[11]) {
+      break;
+    }
+  }
+  assert_eq!(3, chunks.len());
+  chunks.sort();
+  let document: Vec<u8> = chunks.into_iter().flat_map(|(_, chunk)| chunk).collect();
+  let document: serde_json::Value = serde_json::from_slice(&document).unwrap();
+  assert_eq!(long_line, document["short_message"]);
+}
+
+#[cfg(feature = "gelf")]
+#[test]
+fn it_prints_each_message_as_json() {
+  let (server, test_flags) = spawn_test_server();
+
+  let output = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+    .args(&test_flags)
+    .args(["--emit-jsonl", "--priority", "local3.warning", "--hostname", "h", "--appname", "a",
+           "--", "sh", "-c", "echo out; sleep 0.1; echo err >&2; sleep 0.2"])
+    .output()
+    .expect("Unable to run syslog-wrapper.");
+  assert!(output.status.success());
+
+  let records: Vec<serde_json::Value> = String::from_utf8(output.stdout).unwrap().lines()
+    .map(|line| serde_json::from_str(line).unwrap())
+    .collect();
+  assert_eq!(2, records.len());
+  let frames = server.received_frames();
+  for (seq, (record, frame)) in records.iter().zip(&frames).enumerate() {
+    assert_eq!(vec![156, 19, 4], [&record["pri"], &record["facility"], &record["severity"]].map(|v| v.as_u64().unwrap()));
+    assert_eq!("local3.warning", record["priority"]);
+    assert_eq!(frame.timestamp, record["timestamp"]);
+    assert_eq!(("h", "a"), (record["hostname"].as_str().unwrap(), record["appname"].as_str().unwrap()));
+    assert!(record["msgid"].is_null() && record["sd"].is_null());
+    assert_eq!(frame.msg, record["msg"]);
+    assert_eq!(seq + 1, record["seq"].as_u64().unwrap() as usize);
+  }
+  assert_eq!(vec!["stdout", "stderr"], records.iter().map(|record| record["stream"].as_str().unwrap()).collect::<Vec<_>>());
+}
+
+#[test]
+#[cfg(unix)]
+fn it_keeps_stdout_and_stderr_lines_in_order() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--strict-order", "--",
+                 "sh", "-c", "for i in $(seq 1 20); do echo out$i; echo err$i >&2; sleep 0.01; done"])
+      .unwrap();
+
+  let expected: Vec<String> = (1..=20).flat_map(|i| [format!("out{i}"), format!("err{i}")]).collect();
+  assert_eq!(expected, server.received_messages());
+}
+
+#[test]
+#[cfg(unix)]
+fn it_names_the_app_after_the_command_without_its_directory_or_extension() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--appname-from-argv0-basename-without-extension", "--arg0", r"C:\bin\myapp.exe",
+                 "--", "sh", "-c", "echo hi"])
+      .unwrap();
+
+  assert_eq!("myapp", server.received_frames()[0].appname);
+
+  let (server, test_flags) = spawn_test_server();
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--appname-from-argv0-basename-without-extension", "--arg0", "/opt/myapp/",
+                 "--", "sh", "-c", "echo hi"])
+      .unwrap();
+
+  assert_eq!("/opt/myapp/", server.received_frames()[0].appname);
+}
+
+#[test]
+fn it_spells_out_priorities() {
+  use syslog_wrapper::priority::{parse_priority, Priority, DEFAULT_PRIORITY};
+
+  assert_eq!("mail.info", DEFAULT_PRIORITY.name());
+  assert_eq!("kern.emerg", Priority { facility: 0, severity: 0 }.name());
+  assert_eq!("local7.debug", Priority { facility: 23, severity: 7 }.name());
+  for code in 0..192u8 {
+    let priority = Priority { facility: code / 8, severity: code % 8 };
+    assert_eq!(Ok(priority), parse_priority(&priority.name()));
+  }
+}
+
+#[test]
+fn it_writes_a_rotated_local_file() {
+  let (server, test_flags) = spawn_test_server();
+  let local_file = std::env::temp_dir().join(format!("syslog-wrapper-test-{}.log", std::process::id()));
+  let rotated_file = local_file.with_extension("log.1");
+  let local_file_flag = local_file.to_str().unwrap();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--local-file", local_file_flag, "--local-file-format", "plain",
+                 "--local-file-max-bytes", "50", "--local-file-max-files", "1",
+                 "--", "sh", "-c", "echo one; echo two; echo three; sleep 0.2"])
+    .unwrap();
+
+  assert_eq!(vec!["one", "two", "three"], server.received_messages());
+  let current = std::fs::read_to_string(&local_file).expect("The local file should exist.");
+  let rotated = std::fs::read_to_string(&rotated_file).expect("The local file should have been rotated.");
+  std::fs::remove_file(&local_file).unwrap();
+  std::fs::remove_file(&rotated_file).unwrap();
+  assert!(current.ends_with(" three\n"), "Unexpected local file contents `{current}`");
+  assert!(rotated.ends_with(" two\n"), "Unexpected rotated file contents `{rotated}`");
+  assert_eq!(1, rotated.lines().count());
+}
+
+#[test]
+fn it_writes_frames_to_the_local_file_as_the_primary_server_gets_them() {
+  let (server, server_address) = spawn_untrusted_test_server();
+  let local_file = std::env::temp_dir().join(format!("syslog-wrapper-test-{}-wire.log", std::process::id()));
+
+  wrapper()
+    .with_args(&[&format!("{server_address},facility=local5"), "--add-trusted-certificates", "certs/ca.crt",
+                 "--local-file", local_file.to_str().unwrap(), "--", "sh", "-c", "echo one; sleep 0.2"])
+    .unwrap();
+
+  let contents = std::fs::read_to_string(&local_file).expect("The local file should exist.");
+  std::fs::remove_file(&local_file).unwrap();
+  let frames = server.received_frames();
+  assert_eq!(21, frames[0].facility());
+  assert_eq!(frames, vec![Frame::parse(contents.trim_end())]);
+}
+
+#[test]
+fn it_redacts_lines_before_sending_them() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--redact", r"user-\d+=user-X", "--redact-preset", "secrets", "--redact-preset", "pii", "--run-summary",
+                 "--", "sh", "-c", "echo login user-42 password=hunter2 from bob@example.com; sleep 0.2"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(2, frames.len());
+  assert_eq!("login user-X password=[REDACTED] from [EMAIL]", frames[0].msg);
+  // Each match replaced counts
+  assert!(frames[1].sd.contains("redactions=\"3\""), "Unexpected summary `{}`", frames[1].sd);
+
+  // Only numbers with a valid check digit are taken for card numbers
+  let (server, test_flags) = spawn_test_server();
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--redact-preset", "pii", "--", "sh", "-c", "echo paid with 4111 1111 1111 1111 at 1697289600000; sleep 0.2"])
+    .unwrap();
+  assert_eq!(vec!["paid with [CARD] at 1697289600000"], server.received_messages());
+}
+
+#[test]
+fn it_rejects_invalid_redaction_rules() {
+  wrapper()
+    .with_args(&["localhost", "--redact", "(unclosed=x", "--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("Invalid redaction regex `(unclosed`").unwrap();
+}
+
+#[test]
+fn it_runs_the_command_in_the_given_working_directory() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--workdir", "/", "--", "pwd"])
+    .unwrap();
+
+  assert_eq!(vec!["/"], server.received_messages());
+}
+
+#[test]
+fn it_sets_the_command_environment() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_env(Environment::inherit().insert("INHERITED", "yes"))
+    .with_args(&test_flags)
+    .with_args(&["--env-clear", "--env", "GREETING=hello=world", "--", "env"])
+    .unwrap();
+
+  assert_eq!(vec!["GREETING=hello=world"], server.received_messages());
+}
+
+#[test]
+fn it_asks_the_command_not_to_use_color() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--env-clear", "--child-no-color", "--env", "TERM=vt100", "--", "env"])
+    .unwrap();
+
+  let mut messages = server.received_messages();
+  messages.sort();
+  assert_eq!(vec!["NO_COLOR=1", "TERM=vt100"], messages);
+}
+
+#[test]
+fn it_forwards_its_own_stdin_too() {
+  let (server, test_flags) = spawn_test_server();
+
+  // The command gets nothing on its stdin, so cat prints nothing
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--read-stdin-also", "--", "sh", "-c", "sleep 0.3; cat; echo out; sleep 0.2"])
+    .stdin("operator note\n")
+    .unwrap();
+
+  assert_eq!(vec!["operator note", "out"], server.received_messages());
+}
+
+#[test]
+fn it_rejects_malformed_environment_variables() {
+  wrapper()
+    .with_args(&["localhost", "--env", "GREETING", "--", "env"])
+      .fails_with(2).and()
+      .stderr().contains("is not of the form KEY=VALUE").unwrap();
+}
+
+#[test]
+fn it_rejects_unknown_users_and_groups() {
+  wrapper()
+    .with_args(&["localhost", "--user", "no-such-user-here", "--", "id"])
+      .fails_with(2).and()
+      .stderr().contains("Unknown user `no-such-user-here`").unwrap();
+  wrapper()
+    .with_args(&["localhost", "--group", "no-such-group-here", "--", "id"])
+      .fails_with(2).and()
+      .stderr().contains("Unknown group `no-such-group-here`").unwrap();
+}
+
+#[test]
+fn it_writes_and_removes_a_pidfile() {
+  let (server, test_flags) = spawn_test_server();
+  let pidfile = std::env::temp_dir().join(format!("syslog-wrapper-test-{}.pid", std::process::id()));
+  let pidfile = pidfile.to_str().unwrap();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--pidfile", pidfile, "--", "sh", "-c", &format!("echo $$; sleep 0.1; cat {pidfile}; sleep 0.2")])
+    .unwrap();
+
+  let messages = server.received_messages();
+  assert_eq!(2, messages.len());
+  assert_eq!(messages[0], messages[1]);
+  assert!(!std::path::Path::new(pidfile).exists(), "The pidfile should be removed on exit.");
+}
+
+#[test]
+fn it_rate_limits_each_key_separately() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--rate-limit", "1", "--rate-limit-key", "word:1", "--drop-warn-interval", "1",
+                 "--", "sh", "-c", "for i in 1 2 3 4 5; do echo ERROR $i; done; echo INFO 1; sleep 1.5"])
+      .stderr().contains("dropped 4 messages in last 1s (rate-limit: 4 [ERROR: 4])").unwrap();
+
+  assert_eq!(vec!["ERROR 1", "INFO 1"], server.received_messages());
+}
+
+#[test]
+fn it_waits_for_a_server_that_starts_late() {
+  let listener = TcpListener::bind("localhost:0").expect("Unable to pick a port.");
+  let port = listener.local_addr().expect("No local address.").port();
+  drop(listener);
+
+  let wrapper = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+    .args([format!("localhost:{port}").as_str(), "--add-trusted-certificates", "certs/ca.crt", "--wait-for-server", "10"])
+    .args(["--", "sh", "-c", "echo early; sleep 0.2"])
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .expect("Unable to spawn syslog-wrapper.");
+  std::thread::sleep(Duration::from_secs(1));
+  let server = spawn_test_server_on(port);
+
+  let output = wrapper.wait_with_output().unwrap();
+  let stderr = String::from_utf8(output.stderr).unwrap();
+  assert!(output.status.success(), "Unexpected stderr: {stderr}");
+  assert!(stderr.contains("Waiting for `localhost:"), "Unexpected stderr: {stderr}");
+  assert_eq!(vec!["early"], server.received_messages());
+}
+
+#[test]
+fn it_echoes_lines_it_could_not_deliver() {
+  let listener = TcpListener::bind("localhost:0").expect("Unable to pick a port.");
+  let server_address = format!("localhost:{}", listener.local_addr().expect("No local address.").port());
+  drop(listener);
+
+  // Lines printed before the connection attempt failed are echoed along with those printed after
+  wrapper()
+    .with_args(&[server_address.as_str(), "--echo-on-failure", "--", "sh", "-c", "echo one; echo two"])
+      .fails_with(127).and()
+      .stderr().contains("Unable to connect").and()
+      .stderr().contains("one\ntwo\n").unwrap();
+
+  // Echoed lines weren't forwarded
+  wrapper()
+    .with_args(&[server_address.as_str(), "--echo-on-failure", "--run-summary", "--", "sh", "-c", "echo one; echo two"])
+      .fails_with(127).and()
+      .stderr().contains("Forwarded 0 of 2 lines").unwrap();
+
+  // As the command wrote them, not as they would have been sent
+  wrapper()
+    .with_args(&[server_address.as_str(), "--echo-on-failure", "--encode-msg", "base64", "--", "echo", "one"])
+      .fails_with(127).and()
+      .stderr().contains("one\n").and()
+      .stderr().doesnt_contain("b25l").unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn it_delivers_over_an_inherited_socket() {
+  let (server, server_address) = spawn_untrusted_test_server();
+  let port = server_address.rsplit_once(':').unwrap().1;
+
+  // Port 1 is never dialed; the name is only used to check the certificate
+  let script = format!("exec 3<>/dev/tcp/127.0.0.1/{port}; exec \"$0\" localhost:1 --use-fd 3 \
+                        --add-trusted-certificates certs/ca.crt -- sh -c 'echo via fd; sleep 0.2'");
+  let status = Command::new("bash")
+    .args(["-c", &script, env!("CARGO_BIN_EXE_syslog-wrapper")])
+    .status()
+    .expect("Unable to spawn bash.");
+
+  assert!(status.success());
+  assert_eq!(vec!["via fd"], server.received_messages());
+}
+
+#[test]
+#[cfg(unix)]
+fn it_does_not_retry_an_inherited_socket() {
+  // Accepts connections, but hangs up on each before the TLS handshake
+  let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to pick a port.");
+  let port = listener.local_addr().unwrap().port();
+  std::thread::spawn(move || {
+    for connection in listener.incoming() {
+      drop(connection);
+    }
+  });
+
+  // The failed handshake closed fd 3, so trying it again could only pick up someone else's
+  let script = format!("exec 3<>/dev/tcp/127.0.0.1/{port}; exec \"$0\" localhost:1 --use-fd 3 -- echo hi");
+  let output = Command::new("bash")
+    .args(["-c", &script, env!("CARGO_BIN_EXE_syslog-wrapper")])
+    .output()
+    .expect("Unable to spawn bash.");
+
+  assert_eq!(Some(127), output.status.code());
+  let stderr = String::from_utf8(output.stderr).unwrap();
+  assert!(!stderr.contains("Waiting for"), "Unexpected stderr: {stderr}");
+}
+
+#[test]
+fn it_sheds_lines_while_the_queue_is_backed_up() {
+  let (server, test_flags) = spawn_test_server();
+
+  // The command writes far faster than every line can be flushed out to the server. (Keep the
+  // output small enough to fit in the test server's stdout pipe, which isn't read until the end.)
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--adaptive-shed", "--shed-high-water", "50", "--shed-low-water", "5", "--flush-on-each-line",
+                 "--hostname", "h", "--appname", "a", "--drop-warn-interval", "1",
+                 "--", "sh", "-c", "seq 1 1000; sleep 1.5"])
+      .stderr().contains("lines are waiting to be delivered; forwarding only one in 10").and()
+      .stderr().contains("adaptive-shed: ").unwrap();
+
+  let messages = server.received_messages();
+  assert_eq!("1", messages[0]);
+  assert!(messages.len() < 1000, "Nothing was shed.");
+}
+
+#[test]
+fn it_paces_bursts_and_delivers_the_rest_once_the_command_exits() {
+  let (server, test_flags) = spawn_test_server();
+
+  // Ten lines at five a second would take two seconds, but the command is gone after one
+  let started = std::time::Instant::now();
+  let status = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+    .args(&test_flags)
+    .args(["--pace-fps", "5", "--timestamp-precision", "millis", "--", "sh", "-c", "seq 1 10; sleep 1"])
+    .status()
+    .expect("Unable to run syslog-wrapper.");
+  assert!(status.success());
+  assert!(started.elapsed() < Duration::from_millis(1900), "The rest wasn't delivered promptly.");
+
+  let frames = server.received_frames();
+  assert_eq!(10, frames.len());
+  let times: Vec<_> = frames.iter()
+    .map(|frame| chrono::DateTime::parse_from_rfc3339(&frame.timestamp).unwrap())
+    .collect();
+  for pair in times[..4].windows(2) {
+    assert!(pair[1] - pair[0] >= chrono::Duration::milliseconds(150), "Lines weren't paced: {times:?}");
+  }
+}
+
+#[test]
+#[cfg(unix)]
+fn it_returns_without_waiting_for_the_command() {
+  let (server, test_flags) = spawn_test_server();
+
+  // The detached wrapper holds on to stdout and stderr, so don't wait for them to close
+  let started = std::time::Instant::now();
+  let status = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+    .args(&test_flags)
+    .args(["--no-wait-child", "--", "sh", "-c", "sleep 2; echo later; sleep 0.2"])
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+    .expect("Unable to spawn syslog-wrapper.");
+
+  assert!(status.success());
+  assert!(started.elapsed() < Duration::from_secs(2), "The wrapper waited for the command.");
+  assert_eq!(vec!["later"], server.received_messages());
+}
+
+#[test]
+#[cfg(unix)]
+fn it_passes_on_the_failure_when_detached_delivery_cannot_start() {
+  let (_server, server_address) = spawn_untrusted_test_server();
+
+  // The certificate is only checked during the TLS handshake, which has to finish first
+  let status = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+    .args([server_address.as_str(), "--no-wait-child", "--", "echo", "hi"])
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .status()
+    .expect("Unable to spawn syslog-wrapper.");
+
+  assert_eq!(Some(127), status.code());
+}
+
+#[test]
+#[cfg(unix)]
+fn it_exits_like_a_shell_when_the_command_is_killed() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--", "sh", "-c", "echo dying; kill -9 $$"])
+      .fails_with(137).unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(vec!["-", "KILLED"], frames.iter().map(|frame| frame.msgid.as_str()).collect::<Vec<_>>());
+  assert_eq!("The command was killed by SIGKILL", frames[1].msg);
+}
+
+#[test]
+#[cfg(unix)]
+fn it_emits_a_message_when_signalled() {
+  let (mut server, test_flags) = spawn_test_server();
+
+  let wrapper = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+    .args(&test_flags)
+    .args(["--emit-on-signal", "SIGUSR2=manual checkpoint", "--", "sh", "-c", "echo before; sleep 1; echo after; sleep 0.2"])
+    .spawn()
+    .expect("Unable to spawn syslog-wrapper.");
+  server.wait_for_message("before");
+  Command::new("kill").args(["-USR2", &wrapper.id().to_string()]).status().unwrap();
+
+  assert!(wrapper.wait_with_output().unwrap().status.success());
+  let frames = server.received_frames();
+  assert_eq!(vec!["-", "SIGNAL", "-"], frames.iter().map(|frame| frame.msgid.as_str()).collect::<Vec<_>>());
+  assert_eq!("manual checkpoint", frames[1].msg);
+}
+
+#[test]
+#[cfg(unix)]
+fn it_refuses_to_emit_messages_for_signals_that_cannot_be_handled() {
+  wrapper()
+    .with_args(&["localhost", "--emit-on-signal", "KILL=bye", "--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("SIGKILL can't be handled").unwrap();
+  wrapper()
+    .with_args(&["localhost", "--emit-on-signal", "SIGSTOP=bye", "--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("SIGSTOP can't be handled").unwrap();
 }
 
 #[test]
 fn it_retries_on_socket_failures() {
-  // TODO: Make sure the errors go to actual stderr
-  assert!(false, "Pending test");
+  let (mut first_server, server_address) = spawn_untrusted_test_server();
+  let port: u16 = server_address.rsplit_once(':').unwrap().1.parse().unwrap();
+
+  let wrapper = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+    .args([server_address.as_str(), "--add-trusted-certificates", "certs/ca.crt", "--min-reconnect-interval", "200"])
+    .args(["--", "sh", "-c", "echo one; sleep 1; echo two; sleep 0.5; echo three; sleep 0.5; echo four; sleep 0.2"])
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped())
+    .spawn()
+    .expect("Unable to spawn syslog-wrapper.");
+
+  // Take the server down once the first line arrives, and bring up another in its place
+  first_server.wait_for_message("one");
+  first_server.process.kill().unwrap();
+  first_server.process.wait().unwrap();
+  let second_server = spawn_test_server_on(port);
+
+  let output = wrapper.wait_with_output().unwrap();
+  assert!(output.status.success());
+  let stderr = String::from_utf8(output.stderr).unwrap();
+  assert!(stderr.contains("Lost connection to"), "Unexpected stderr: {stderr}");
+  assert!(stderr.contains("Reconnecting to"), "Unexpected stderr: {stderr}");
+  // Whatever the kernel accepted before the connection was known to be broken is lost, but the
+  // line that found out, and the one before it, are re-sent
+  assert_eq!(vec!["three", "four"], second_server.received_messages());
+}
+
+#[test]
+fn it_announces_lost_and_reestablished_connections() {
+  let (mut first_server, server_address) = spawn_untrusted_test_server();
+  let port: u16 = server_address.rsplit_once(':').unwrap().1.parse().unwrap();
+
+  let wrapper = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+    .args([server_address.as_str(), "--add-trusted-certificates", "certs/ca.crt", "--min-reconnect-interval", "200",
+           "--connection-events"])
+    .args(["--", "sh", "-c", "echo one; sleep 1; echo two; sleep 0.5; echo three; sleep 0.5; echo four; sleep 0.2"])
+    .stdout(Stdio::null())
+    .stderr(Stdio::null())
+    .spawn()
+    .expect("Unable to spawn syslog-wrapper.");
+
+  first_server.wait_for_message("one");
+  first_server.process.kill().unwrap();
+  first_server.process.wait().unwrap();
+  let second_server = spawn_test_server_on(port);
+
+  let output = wrapper.wait_with_output().unwrap();
+  assert!(output.status.success());
+  let frames = second_server.received_frames();
+  let events: Vec<_> = frames.iter().filter(|frame| frame.msgid.starts_with("CONN_")).collect();
+  assert_eq!(vec!["CONN_DOWN", "CONN_UP"], events.iter().map(|frame| frame.msgid.as_str()).collect::<Vec<_>>());
+  let peer = format!(r#"peer="{server_address}""#);
+  assert!(events.iter().all(|frame| frame.sd.contains(&peer)), "Unexpected events: {events:?}");
+  assert!(events[1].sd.contains("downtime-ms=") && events[1].sd.contains("attempts="), "Unexpected event: {:?}", events[1]);
+}
+
+#[test]
+fn it_gives_up_quickly_when_nothing_has_been_delivered_yet() {
+  // Accepts connections, but hangs up on each before the TLS handshake
+  let listener = TcpListener::bind("localhost:0").expect("Unable to pick a port.");
+  let server_address = format!("localhost:{}", listener.local_addr().unwrap().port());
+  std::thread::spawn(move || {
+    for connection in listener.incoming() {
+      drop(connection);
+    }
+  });
+
+  wrapper()
+    .with_args(&[server_address.as_str(), "--connect-retries-are-fatal-after", "1", "--max-retries", "10",
+                 "--", "echo", "hi"])
+      .fails_with(127).and()
+      .stderr().contains("Waiting for").and()
+      .stderr().contains("Unable to connect to").unwrap();
+}
+
+#[test]
+fn it_times_out_a_stalled_handshake() {
+  // Accepts connections, but never says a word
+  let listener = TcpListener::bind("localhost:0").expect("Unable to pick a port.");
+  let server_address = format!("localhost:{}", listener.local_addr().unwrap().port());
+  std::thread::spawn(move || {
+    let _connections: Vec<_> = listener.incoming().collect();
+  });
+
+  wrapper()
+    .with_args(&[server_address.as_str(), "--handshake-timeout", "1", "--connect-retries-are-fatal-after", "0",
+                 "--", "echo", "hi"])
+      .fails_with(127).and()
+      .stderr().contains("the TLS handshake timed out").unwrap();
+}
+
+#[test]
+#[cfg(unix)]
+fn it_connects_from_a_port_in_the_source_port_range() {
+  let listener = TcpListener::bind("127.0.0.1:0").expect("Unable to pick a port.");
+  let server_address = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+  let source_port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+  let (peer_port_sender, peer_port) = std::sync::mpsc::channel();
+  std::thread::spawn(move || {
+    let (_socket, peer) = listener.accept().unwrap();
+    peer_port_sender.send(peer.port()).unwrap();
+  });
+
+  // The listener doesn't speak TLS, so delivery fails once it's connected
+  wrapper()
+    .with_args(&[server_address.as_str(), "--source-port-range", &format!("{source_port}-{source_port}"),
+                 "--connect-retries-are-fatal-after", "0", "--", "echo", "hi"])
+      .fails_with(127).unwrap();
+  assert_eq!(source_port, peer_port.recv_timeout(Duration::from_secs(5)).unwrap());
+
+  // With the only port in the range taken, there's nothing to connect from
+  let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+  let taken_port = taken.local_addr().unwrap().port();
+  wrapper()
+    .with_args(&[server_address.as_str(), "--source-port-range", &format!("{taken_port}-{taken_port}"),
+                 "--connect-retries-are-fatal-after", "0", "--", "echo", "hi"])
+      .fails_with(127).and()
+      .stderr().contains(format!("no port in the source port range {taken_port}-{taken_port} was free").as_str())
+      .unwrap();
+}
+
+#[test]
+fn it_stops_waiting_for_delivery_some_time_after_the_command_exits() {
+  // Accepts connections, but never says a word, so delivery never gets anywhere
+  let listener = TcpListener::bind("localhost:0").expect("Unable to pick a port.");
+  let server_address = format!("localhost:{}", listener.local_addr().unwrap().port());
+  std::thread::spawn(move || {
+    let _connections: Vec<_> = listener.incoming().collect();
+  });
+
+  let started = std::time::Instant::now();
+  wrapper()
+    .with_args(&[server_address.as_str(), "--post-exit-timeout", "1", "--", "echo", "hi"])
+      .fails_with(127).and()
+      .stderr().contains("Delivery still hadn't finished 1s after the command exited").unwrap();
+  assert!(started.elapsed() < Duration::from_secs(5), "The wrapper kept waiting for delivery.");
+}
+
+#[test]
+fn it_reports_each_frame_delivered_through_the_library() {
+  let (server, server_address) = spawn_untrusted_test_server();
+  let root_store = syslog_wrapper::trust::build_root_store(Some(std::path::Path::new("certs/ca.crt")), None).unwrap();
+  let config = rustls::ClientConfig::builder()
+    .with_safe_defaults()
+    .with_root_certificates(root_store)
+    .with_no_client_auth();
+  let options = Options {
+    framing: Framing::OctetCounting,
+    flush_on_each_line: false,
+    max_retries: 0,
+    startup_retries: 0,
+    min_reconnect_interval: Duration::ZERO,
+    wait_for_server: Duration::ZERO,
+    dns_cache_ttl: Duration::ZERO,
+    handshake_timeout: None,
+    backoff_reset_after: None,
+    source_ports: None,
+  };
+  let destination = Destination::parse(&server_address).unwrap();
+  let mut sender = Sender::connect(destination, Transport::Tls, Arc::new(config), options, Arc::new(Stats::default()))
+    .expect("Unable to connect to the test server.");
+
+  let delivered = Arc::new(Mutex::new(vec![]));
+  let callback_delivered = delivered.clone();
+  sender.on_delivered(move |frame| callback_delivered.lock().unwrap().push(frame.to_string()));
+  let frames = ["<22>1 - h a - - - one\n", "<22>1 - h a - - - two\n"];
+  for frame in frames {
+    sender.send(frame).expect("Unable to send to the test server.");
+  }
+  sender.close();
+
+  assert_eq!(frames.to_vec(), *delivered.lock().unwrap());
+  assert_eq!(vec!["one", "two"], server.received_messages());
+}
+
+#[test]
+fn it_base64_encodes_messages_on_request() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--encode-msg", "base64", "--", "sh", "-c", "printf 'a\\377b\\n'; echo plain; sleep 0.2"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(2, frames.len());
+  for frame in &frames {
+    assert_eq!("[encoding@32473 type=\"base64\"]", frame.sd);
+  }
+  assert_eq!(vec!["Yf9i", "cGxhaW4="], frames.iter().map(|frame| frame.msg.as_str()).collect::<Vec<_>>());
+}
+
+#[test]
+fn it_base64_encodes_duplicate_summaries_too() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--dedup", "--encode-msg", "base64", "--", "sh", "-c", "echo a; echo a; echo b; sleep 0.2"])
+    .unwrap();
+
+  let messages: Vec<String> = server.received_frames().iter()
+    .map(|frame| String::from_utf8(base64::engine::general_purpose::STANDARD.decode(&frame.msg).unwrap()).unwrap())
+    .collect();
+  assert_eq!(vec!["a", "last message repeated 1 times", "b"], messages);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn it_runs_the_command_with_a_custom_arg0() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--arg0", "custom-name",
+                 "--", "sh", "-c", "tr '\\0' '\\n' < /proc/$$/cmdline | head -n 1; sleep 0.2"])
+    .unwrap();
+
+  // The default appname follows argv[0], too
+  let frames = server.received_frames();
+  assert_eq!(1, frames.len());
+  assert_eq!("custom-name", frames[0].appname);
+  assert_eq!("custom-name", frames[0].msg);
 }
 
 #[test]
 fn it_honors_hostname_flag() {
-  assert!(false, "Pending test");
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--hostname", "pleasant-cornfield-5", "--", "echo", "hi"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(1, frames.len());
+  assert_eq!("pleasant-cornfield-5", frames[0].hostname);
+}
+
+#[test]
+fn it_numbers_messages_in_delivery_order() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--sequence-sd", "--", "seq", "1", "3"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(vec!["[seq@32473 n=\"1\"]", "[seq@32473 n=\"2\"]", "[seq@32473 n=\"3\"]"],
+             frames.iter().map(|frame| frame.sd.as_str()).collect::<Vec<_>>());
+}
+
+#[test]
+fn it_summarizes_the_run_last() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--run-summary", "--", "sh", "-c", "echo one; echo two; exit 3"])
+      .fails_with(3).unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(vec!["-", "-", "SUMMARY"], frames.iter().map(|frame| frame.msgid.as_str()).collect::<Vec<_>>());
+  let summary = &frames[2];
+  for param in ["lines-read=\"2\"", "lines-forwarded=\"2\"", "bytes-read=\"8\"", "reconnects=\"0\"",
+                "redactions=\"0\"", "drops-rate-limit=\"0\"", "drops-adaptive-shed=\"0\"", "exit-code=\"3\""] {
+    assert!(summary.sd.contains(param), "Missing {param} in `{}`", summary.sd);
+  }
+  assert!(summary.msg.starts_with("Forwarded 2 of 2 lines in "), "Unexpected summary `{}`", summary.msg);
+}
+
+#[test]
+fn it_honors_syslog_version_flag() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--syslog-version", "1", "--", "echo", "hi"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(1, frames.len());
+  assert_eq!("1", frames[0].version);
+
+  for version in ["42", "1000", "one"] {
+    wrapper()
+      .with_args(&["localhost", "--syslog-version", version, "--", "ls"])
+        .fails_with(2).and()
+        .stderr().contains("only version 1 can be written").unwrap();
+  }
 }
 
 #[test]
 fn it_honors_process_name_flag() {
-  assert!(false, "Pending test");
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--appname", "carriage-cobbler", "--", "echo", "hi"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(1, frames.len());
+  assert_eq!("carriage-cobbler", frames[0].appname);
+}
+
+#[test]
+fn it_truncates_long_appnames_unless_asked_not_to() {
+  let appname = "a".repeat(60);
+
+  let (server, test_flags) = spawn_test_server();
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--appname", &appname, "--", "sh", "-c", "echo truncated; sleep 0.2"])
+    .unwrap();
+  assert_eq!("a".repeat(48), server.received_frames()[0].appname);
+
+  let (server, test_flags) = spawn_test_server();
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--appname", &appname, "--no-truncate-appname", "--", "sh", "-c", "echo in full; sleep 0.2"])
+    .unwrap();
+  assert_eq!(appname, server.received_frames()[0].appname);
+}
+
+#[test]
+fn it_refuses_non_compliant_header_fields_when_strict() {
+  wrapper()
+    .with_args(&["localhost", "--strict-rfc", "--no-truncate-hostname", "--hostname", &"h".repeat(300), "--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("is 300 bytes long, over the 255 RFC 5424 allows").unwrap();
+  wrapper()
+    .with_args(&["localhost", "--strict-rfc", "--appname", "my app", "--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("The app-name `my app` contains ' '").unwrap();
+}
+
+#[test]
+fn it_prefixes_the_message_with_its_timestamp() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--prefix-timestamp-in-msg", "--timestamp-precision", "millis", "--", "sh", "-c", "echo event; sleep 0.2"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(1, frames.len());
+  let timestamp = &frames[0].timestamp;
+  assert_eq!(Some("+00:00"), timestamp.split_once('.').map(|(_, fraction)| &fraction[3..]));
+  assert_eq!(format!("{timestamp} event"), frames[0].msg);
+}
+
+#[test]
+fn it_attaches_each_given_sd_element_to_every_message() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--sd", "exampleSDID@32473 iut=3 eventSource=Application",
+                 "--sd", r#"meta@32473 note="two \"quoted\" words]""#,
+                 "--", "sh", "-c", "echo one; echo two"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(2, frames.len());
+  for frame in &frames {
+    assert_eq!(r#"[exampleSDID@32473 iut="3" eventSource="Application"][meta@32473 note="two \"quoted\" words\]"]"#,
+               frame.sd);
+  }
+}
+
+#[test]
+fn it_rejects_invalid_sd_elements() {
+  for (sd, error) in [
+    (vec!["bad]id x=1"], "`bad]id` contains ']'"),
+    (vec!["meta@32473 =1"], "`` is not 1 to 32 characters long"),
+    (vec!["meta@32473 x"], "`x` is not of the form NAME=VALUE"),
+    (vec![r#"meta@32473 x="open"#], "The value of `x` has no closing quote"),
+    (vec!["meta@32473 x=1", "meta@32473 y=2"], "The SD-ID `meta@32473` is used more than once"),
+    (vec!["seq@32473 n=1"], "The SD-ID `seq@32473` is used more than once"),
+    (vec!["conn@32473 peer=x"], "The SD-ID `conn@32473` is used more than once"),
+  ] {
+    let args: Vec<&str> = sd.iter().flat_map(|element| ["--sd", element]).chain(["localhost", "--", "true"]).collect();
+    wrapper()
+      .with_args(&args)
+        .fails_with(2).and()
+        .stderr().contains(error).unwrap();
+  }
+}
+
+#[test]
+fn it_captures_the_environment_on_the_first_message() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--capture-env", "DEPLOY_ID,NOT_SET_ANYWHERE", "--env", r#"DEPLOY_ID=a "b" ]c"#,
+                 "--", "sh", "-c", "echo one; echo two"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(2, frames.len());
+  assert_eq!(r#"[env@32473 DEPLOY_ID="a \"b\" \]c"][command@32473 line="sh -c echo one; echo two"]"#, frames[0].sd);
+  assert_eq!("-", frames[1].sd);
+
+  // Without any of the variables set, there's just the command line
+  let (server, test_flags) = spawn_test_server();
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--capture-env", "NOT_SET_ANYWHERE", "--", "echo", "hi"])
+    .unwrap();
+  assert_eq!(r#"[command@32473 line="echo hi"]"#, server.received_frames()[0].sd);
+
+  // Variables --child-no-color sets are recorded as the command sees them, under any --env
+  let (server, test_flags) = spawn_test_server();
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--capture-env", "NO_COLOR,TERM", "--child-no-color", "--env", "NO_COLOR=", "--", "echo", "hi"])
+    .with_env(Environment::inherit().insert("TERM", "xterm"))
+    .unwrap();
+  assert_eq!(r#"[env@32473 NO_COLOR="" TERM="dumb"][command@32473 line="echo hi"]"#, server.received_frames()[0].sd);
+}
+
+#[test]
+fn it_starts_every_message_with_the_given_prefix() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_args(&test_flags)
+    .with_args(&["--msg-prefix", "type=app ", "--prefix-timestamp-in-msg", "--", "sh", "-c", "echo event; sleep 0.2"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(1, frames.len());
+  assert_eq!(format!("type=app {} event", frames[0].timestamp), frames[0].msg);
+}
+
+#[test]
+fn it_treats_blank_hostname_and_appname_variables_as_unset() {
+  let (server, test_flags) = spawn_test_server();
+
+  wrapper()
+    .with_env(Environment::inherit().insert("SYSLOG_HOSTNAME", "").insert("SYSLOG_APPNAME", ""))
+    .with_args(&test_flags)
+    .with_args(&["--", "echo", "hi"])
+    .unwrap();
+
+  let frames = server.received_frames();
+  assert_eq!(1, frames.len());
+  assert_ne!("-", frames[0].hostname, "Hostname should fall back to the system hostname");
+  assert_eq!("echo", frames[0].appname);
+}
+
+#[test]
+fn it_treats_a_blank_server_variable_as_unset() {
+  wrapper()
+    .with_env(Environment::inherit().insert("SYSLOG_SERVER", ""))
+    .with_args(&["--", "ls"])
+      .fails_with(2).and()
+      .stderr().contains("must not be empty").unwrap();
 }
 
 #[test]
 fn it_fails_if_certificate_is_not_trusted() {
-  assert!(false, "Pending test");
+  let (_server, server_address) = spawn_untrusted_test_server();
+
+  // Retrying wouldn't help, so it gives up without waiting through the backoff
+  let started = std::time::Instant::now();
+  let output = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+    .args([server_address.as_str(), "--", "echo", "hi"])
+    .output()
+    .expect("Unable to run syslog-wrapper.");
+  assert_eq!(Some(127), output.status.code());
+  assert!(started.elapsed() < Duration::from_secs(5), "The wrapper retried.");
+  let stderr = String::from_utf8(output.stderr).unwrap();
+  assert!(stderr.contains("presented a certificate that can't be trusted, so not retrying"), "Unexpected stderr: {stderr}");
+  assert!(!stderr.contains("Reconnecting"), "Unexpected stderr: {stderr}");
 }
 
 #[test]
 fn it_does_not_crash_on_super_long_lines() {
-  assert!(false, "Pending test");
+  panic!("Pending test");
 }
 
 #[test]
 fn it_forwards_signals_to_child() {
-  assert!(false, "Pending test");
+  panic!("Pending test");
 }
 
-fn spawn_test_server() -> (Child, Vec<String>) {
+struct TestServer {
+  process: Child,
+  output: BufReader<ChildStdout>,
+  /// What `wait_for_message` has read of the output so far.
+  received: Vec<u8>,
+}
+
+impl TestServer {
+  /// Reads the test server's output until it has received a frame with the given MSG.
+  fn wait_for_message(&mut self, message: &str) {
+    while !parse_frames(&self.received).iter().any(|frame| Frame::parse(frame).msg == message) {
+      let mut chunk = [0; 4096];
+      let len = self.output.read(&mut chunk).expect("Unable to read test server output.");
+      assert!(len > 0, "Test server exited before receiving `{message}`.");
+      self.received.extend_from_slice(&chunk[..len]);
+    }
+  }
+
+  /// Waits for the test server to finish its connection and returns each syslog frame it
+  /// received.
+  fn received_frames(self) -> Vec<Frame> {
+    parse_frames(self.received_output().as_bytes()).iter().map(|frame| Frame::parse(frame)).collect()
+  }
+
+  /// Waits for the test server to finish its connection and returns everything it printed.
+  fn received_output(mut self) -> String {
+    self.process.wait().expect("Test server did not exit cleanly.");
+    self.output.read_to_end(&mut self.received).expect("Unable to read test server output.");
+    String::from_utf8_lossy(&self.received).into_owned()
+  }
+
+  /// Like `received_frames`, but returns only the MSG of each frame.
+  fn received_messages(self) -> Vec<String> {
+    self.received_frames().into_iter().map(|frame| frame.msg).collect()
+  }
+}
+
+/// Picks the octet-counted (`LEN SP MSG`) syslog frames out of the test server's output, which
+/// may also have the server's own output mixed in. An incomplete frame at the end is left out.
+fn parse_frames(output: &[u8]) -> Vec<String> {
+  let mut frames = vec![];
+  let mut rest = output;
+  while let Some(start) = (0..rest.len()).find(|&i| is_frame_start(&rest[i..])) {
+    let digits = rest[start..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let len: usize = std::str::from_utf8(&rest[start..start + digits]).unwrap().parse().unwrap();
+    let message_start = start + digits + 1;
+    if message_start + len > rest.len() {
+      break;
+    }
+    frames.push(String::from_utf8_lossy(&rest[message_start..message_start + len]).into_owned());
+    rest = &rest[message_start + len..];
+  }
+  frames
+}
+
+fn is_frame_start(bytes: &[u8]) -> bool {
+  let digits = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+  digits > 0 && bytes[digits..].starts_with(b" <")
+}
+
+fn spawn_test_server() -> (TestServer, Vec<String>) {
+  let (server, server_address) = spawn_untrusted_test_server();
+  (server, vec![server_address, "--add-trusted-certificates".to_string(), "certs/ca.crt".to_string()])
+}
+
+/// Spawns the test server without adding any flags to trust its certificate.
+fn spawn_untrusted_test_server() -> (TestServer, String) {
   let listener = TcpListener::bind("localhost:0").expect("Unable to pick a port.");
   let port = listener.local_addr().expect("No local address.").port();
-  // TODO: Automatically run minica
-  let server_command = Command::new("./test-server.sh")
-        .arg(format!("{port}"))
+  drop(listener);
+  (spawn_test_server_on(port), format!("localhost:{port}"))
+}
+
+/// Spawns the wrapper's own test server, which serves a single connection and then exits.
+fn spawn_test_server_on(port: u16) -> TestServer {
+  let mut process = Command::new(env!("CARGO_BIN_EXE_syslog-wrapper"))
+        .args(["--test-server", &port.to_string()])
+        .stdin(Stdio::null())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .expect("Unable to spawn test-server.sh during test.");
-        return (server_command, vec![format!("localhost:{port}"),
-                                "--add-trusted-certificates".to_string(), "cacert.crt".to_string()]);
+        .expect("Unable to spawn the test server during test.");
+  let mut output = BufReader::new(process.stdout.take().unwrap());
+  // Don't hand the server over until it's actually listening
+  let mut line = String::new();
+  while !line.starts_with("Listening on port") {
+    line.clear();
+    let len = output.read_line(&mut line).expect("Unable to read test server output.");
+    assert!(len > 0, "Test server exited before accepting connections.");
+  }
+  TestServer { process, output, received: vec![] }
 }
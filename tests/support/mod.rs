@@ -0,0 +1,105 @@
+// Picking apart the RFC 5424 messages the test server receives, so that tests can assert on
+// individual fields rather than on positions in the raw text.
+
+/// One syslog message, split into its fields. Nil fields are left as `-`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+  pub pri: u8,
+  pub version: String,
+  pub timestamp: String,
+  pub hostname: String,
+  pub appname: String,
+  pub procid: String,
+  pub msgid: String,
+  /// The STRUCTURED-DATA, verbatim: `-`, or one or more `[...]` elements.
+  pub sd: String,
+  pub msg: String,
+}
+
+impl Frame {
+  /// Parses a message per RFC 5424 sec. 6, panicking if it's malformed.
+  pub fn parse(frame: &str) -> Frame {
+    let malformed = |problem: &str| -> ! { panic!("Malformed frame ({problem}): `{frame}`") };
+
+    let rest = frame.strip_prefix('<').unwrap_or_else(|| malformed("no PRI"));
+    let (pri, rest) = rest.split_once('>').unwrap_or_else(|| malformed("unterminated PRI"));
+    let pri = pri.parse().unwrap_or_else(|_| malformed("non-numeric PRI"));
+
+    let mut header = rest.splitn(7, ' ');
+    let mut next_field = |name: &str| header.next().unwrap_or_else(|| malformed(&format!("no {name}"))).to_string();
+    let version = next_field("VERSION");
+    let timestamp = next_field("TIMESTAMP");
+    let hostname = next_field("HOSTNAME");
+    let appname = next_field("APP-NAME");
+    let procid = next_field("PROCID");
+    let msgid = next_field("MSGID");
+    let rest = next_field("STRUCTURED-DATA");
+
+    let sd_len = structured_data_len(&rest).unwrap_or_else(|| malformed("bad STRUCTURED-DATA"));
+    let (sd, msg) = rest.split_at(sd_len);
+    let msg = match msg.strip_prefix(' ') {
+      Some(msg) => msg,
+      None if msg.is_empty() => "",
+      None => malformed("no space after STRUCTURED-DATA"),
+    };
+
+    Frame { pri, version, timestamp, hostname, appname, procid, msgid, sd: sd.into(), msg: msg.into() }
+  }
+
+  pub fn facility(&self) -> u8 {
+    self.pri / 8
+  }
+
+  pub fn severity(&self) -> u8 {
+    self.pri % 8
+  }
+}
+
+/// The length of the STRUCTURED-DATA at the start of `text`, or `None` if there isn't any.
+fn structured_data_len(text: &str) -> Option<usize> {
+  if text.starts_with('-') {
+    return Some(1);
+  }
+  let bytes = text.as_bytes();
+  let mut end = 0;
+  while bytes.get(end) == Some(&b'[') {
+    let mut in_value = false;
+    let mut escaped = false;
+    end += 1;
+    loop {
+      match (bytes.get(end)?, in_value, escaped) {
+        (_, true, true) => escaped = false,
+        (b'\\', true, false) => escaped = true,
+        (b'"', _, false) => in_value = !in_value,
+        (b']', false, _) => break,
+        _ => {},
+      }
+      end += 1;
+    }
+    end += 1;
+  }
+  (end > 0).then_some(end)
+}
+
+#[test]
+fn it_parses_a_frame_without_structured_data() {
+  let frame = Frame::parse("<22>1 2003-10-11T22:14:15.003Z mymachine su - - - hello world");
+  assert_eq!((2, 6), (frame.facility(), frame.severity()));
+  assert_eq!("2003-10-11T22:14:15.003Z", frame.timestamp);
+  assert_eq!(("mymachine", "su", "-", "-", "-"),
+             (frame.hostname.as_str(), frame.appname.as_str(), frame.procid.as_str(), frame.msgid.as_str(), frame.sd.as_str()));
+  assert_eq!("hello world", frame.msg);
+}
+
+#[test]
+fn it_parses_structured_data_elements() {
+  let frame = Frame::parse(r#"<165>1 - host app 12 ID47 [a@1 x="q\"]"][b@1] msg"#);
+  assert_eq!(r#"[a@1 x="q\"]"][b@1]"#, frame.sd);
+  assert_eq!("msg", frame.msg);
+  assert_eq!(("12", "ID47"), (frame.procid.as_str(), frame.msgid.as_str()));
+}
+
+#[test]
+fn it_parses_a_frame_without_a_message() {
+  assert_eq!("", Frame::parse("<22>1 - host app - - -").msg);
+}